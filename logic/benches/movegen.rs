@@ -0,0 +1,29 @@
+// Run with: cargo bench -p chess_logic
+use std::sync::Arc;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use chess_logic::{engine::EngineKind, evaluation::SearchSettings, position::Position, unit::Team};
+
+// Floor 10 of seed 7, after spending 60 material on queens in every shop
+const LATE_FLOOR: &str = "______QQQQQ____/______QR...____/______.BKN.____/______.......__/______.......__/_______......._/\
+                          _______r......_/_______......._/_________p.____/_________j.____/_________....__/_________.n..../\
+                          _________.....r/_________...p.</_____________.. p";
+
+fn movegen(c: &mut Criterion) {
+    let position = LATE_FLOOR.parse::<Position>().unwrap();
+    let state = &position.state;
+
+    c.bench_function("valid moves", |b| {
+        b.iter(|| black_box(state.get_valid_moves(Team::Player).len() + state.get_valid_moves(Team::Ai).len()))
+    });
+    c.bench_function("perft 3", |b| {
+        let mut state = state.shallow_clone();
+        b.iter(|| black_box(state.perft(position.side_to_move, 3)))
+    });
+    c.bench_function("minimax depth 3", |b| {
+        let settings = SearchSettings { time_budget_ms: f64::MAX, max_depth: 3, eval_noise: 0.0, seed: 0 };
+        b.iter(|| black_box(EngineKind::Minimax.create().search(state.shallow_clone(), Team::Ai, &settings, Arc::default())))
+    });
+}
+
+criterion_group!(benches, movegen);
+criterion_main!(benches);
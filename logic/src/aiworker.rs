@@ -0,0 +1,80 @@
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, TryRecvError}};
+
+use crate::{boardstate::BoardState, engine::EngineKind, evaluation::{SearchResult, SearchSettings}, unit::Team};
+
+// AI search running in the background. The search is cancelled when this is dropped.
+pub struct AiSearch {
+    receiver: Receiver<SearchResult>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl AiSearch {
+    pub fn start(state: BoardState, team: Team, engine: EngineKind, settings: SearchSettings) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let worker_cancel = Arc::clone(&cancel);
+        let search = move || {
+            let result = engine.create().search(state, team, &settings, worker_cancel);
+            // Receiver is gone if the search was cancelled
+            let _ = sender.send(result);
+        };
+
+        // Threads are not available on the web, search synchronously there
+        #[cfg(target_arch = "wasm32")]
+        search();
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(search);
+
+        AiSearch { receiver, cancel }
+    }
+
+    // Returns the result once the search has finished
+    pub fn poll(&self) -> Option<SearchResult> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(SearchResult::default()),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for AiSearch {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::ivec2;
+    use crate::{boardstate::Move, difficulty::Difficulty, tile::TileMap, unit::*};
+    use super::*;
+
+    #[test]
+    fn background_search_test() {
+        let map_plan = [".....", ".....", "....."];
+        let mut state = BoardState {
+            tilemap: Arc::new(TileMap::from(&map_plan[..])),
+            ..Default::default()
+        };
+        state.units.push(Unit { pos: ivec2(0, 0), unit_type: UnitType::King, team: Team::Player, ..Default::default() });
+        state.units.push(Unit { pos: ivec2(4, 0), unit_type: UnitType::Rook, team: Team::Ai, ..Default::default() });
+        state.rebuild_indices();
+
+        let search = AiSearch::start(state.shallow_clone(), Team::Ai, EngineKind::Minimax, Difficulty::Hard.search_settings(0));
+        let result = loop {
+            if let Some(result) = search.poll() {
+                break result;
+            }
+            std::thread::yield_now();
+        };
+        assert_eq!(result.best_move, Some(Move { from: ivec2(4, 0), to: ivec2(0, 0), ..Default::default() }));
+
+        // Dropping a running search does not block
+        drop(AiSearch::start(state, Team::Ai, EngineKind::Mcts, Difficulty::Brutal.search_settings(0)));
+    }
+}
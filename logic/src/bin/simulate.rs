@@ -0,0 +1,280 @@
+// Plays runs without graphics and prints per-floor statistics as CSV, for balancing unit prices,
+// material rewards and the enemy budget.
+//
+//     cargo run --release -p chess_logic --bin simulate -- --runs 50 --player Minimax --time-ms 50 > stats.csv
+use std::collections::BTreeMap;
+use enum_iterator::Sequence;
+
+use chess_logic::{boardstate::Move, difficulty::Difficulty, engine::EngineKind, evaluation::SearchSettings, run::*, savegame::{parse_enum, parse_number}, unit::*, utils};
+
+const USAGE: &str = "\
+Usage: simulate [options]
+  --runs N                 Number of runs to play (default 20)
+  --seed N                 Seed of the first run, runs use consecutive seeds (default 1)
+  --floor N                Play only floor N, starting with the initial material spent in the shop
+  --player KIND            Scripted, Minimax or Mcts (default Scripted)
+  --player-difficulty D    Search settings of an engine-driven player (default Normal)
+  --difficulty D           Easy, Normal, Hard or Brutal (default Normal)
+  --engine KIND            Minimax or Mcts for the enemy (default Minimax)
+  --classic                Kings can't be left capturable and checkmate ends the run
+  --hit-points             Units have armor and queens and archbishops take two hits to capture
+  --time-ms N              Overrides the time budget of every search
+  --max-turns N            Player moves per floor before the run counts as stuck (default 200)";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Sequence)]
+enum PlayerKind {
+    // Takes the most valuable capture, otherwise walks towards the stairs
+    Scripted,
+    Minimax,
+    Mcts,
+}
+
+struct Options {
+    runs: u64,
+    seed: u64,
+    floor: Option<usize>,
+    player: PlayerKind,
+    player_difficulty: Difficulty,
+    difficulty: Difficulty,
+    engine: EngineKind,
+    classic_rules: bool,
+    hit_points: bool,
+    time_ms: Option<f64>,
+    max_turns: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            runs: 20,
+            seed: 1,
+            floor: None,
+            player: PlayerKind::Scripted,
+            player_difficulty: Difficulty::Normal,
+            difficulty: Difficulty::Normal,
+            engine: EngineKind::Minimax,
+            classic_rules: false,
+            hit_points: false,
+            time_ms: None,
+            max_turns: 200,
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {arg}"));
+        match arg.as_str() {
+            "--runs" => options.runs = parse_number(value()?)?,
+            "--seed" => options.seed = parse_number(value()?)?,
+            "--floor" => options.floor = Some(parse_number(value()?)?),
+            "--player" => options.player = parse_enum(value()?)?,
+            "--player-difficulty" => options.player_difficulty = parse_enum(value()?)?,
+            "--difficulty" => options.difficulty = parse_enum(value()?)?,
+            "--engine" => options.engine = parse_enum(value()?)?,
+            "--classic" => options.classic_rules = true,
+            "--hit-points" => options.hit_points = true,
+            "--time-ms" => options.time_ms = Some(parse_number(value()?)?),
+            "--max-turns" => options.max_turns = parse_number(value()?)?,
+            _ => return Err(format!("unknown option {arg}")),
+        }
+    }
+    if matches!(options.floor, Some(floor) if floor == 0 || floor > LAST_FLOOR) {
+        return Err(format!("floor must be between 1 and {LAST_FLOOR}"));
+    }
+    Ok(options)
+}
+
+#[derive(Default)]
+struct FloorStats {
+    runs: u32,
+    cleared: u32,
+    turns: u32,
+    material_earned: i32,
+    units_lost: u32,
+    deaths: BTreeMap<String, u32>,
+}
+
+fn with_time_budget(mut settings: SearchSettings, options: &Options) -> SearchSettings {
+    if let Some(time_ms) = options.time_ms {
+        settings.time_budget_ms = time_ms;
+    }
+    settings
+}
+
+fn scripted_move(run: &Run) -> Option<Move> {
+    let state = run.board_state();
+    let moves = state.get_valid_moves(Team::Player);
+    let best_capture = moves
+        .iter()
+        .filter_map(|m| state.get_unit_at(&m.capture_pos()).map(|u| (m, material_reward(u.unit_type))))
+        .max_by_key(|(_, reward)| *reward)
+        .map(|(m, _)| *m);
+    let stairs = state.stairs?;
+    // The king stays back unless it is the only one left
+    let king_only = !state.units().iter().any(|u| u.team == Team::Player && u.unit_type != UnitType::King);
+    best_capture.or_else(|| {
+        moves
+            .iter()
+            .filter(|m| king_only || state.get_unit_at(&m.from).unwrap().unit_type != UnitType::King)
+            .min_by_key(|m| utils::dist2(&m.to, &stairs) as i32 - utils::dist2(&m.from, &stairs) as i32)
+            .copied()
+    })
+}
+
+fn player_move(run: &mut Run, options: &Options) -> Option<Move> {
+    let engine = match options.player {
+        PlayerKind::Scripted => return scripted_move(run),
+        PlayerKind::Minimax => EngineKind::Minimax,
+        PlayerKind::Mcts => EngineKind::Mcts,
+    };
+    let settings = with_time_budget(options.player_difficulty.search_settings(run.seed ^ run.board_state().hash), options);
+    engine.create().search(run.board_state(), Team::Player, &settings, Default::default()).best_move
+}
+
+fn ai_move(run: &mut Run, options: &Options) -> Option<Move> {
+    let settings = with_time_budget(run.ai_search_settings(), options);
+    let result = run.engine.create().search(run.board_state(), Team::Ai, &settings, Default::default());
+    run.choose_ai_move(result.best_move)
+}
+
+// Buys the most expensive units that are affordable
+fn go_shopping(run: &mut Run) {
+    run.show_shop();
+    while let Some(unit_type) = shop_units().into_iter().filter(|u| unit_buy_price(*u) <= run.material).max_by_key(|u| unit_buy_price(*u)) {
+        if !run.buy_unit(unit_type) {
+            break
+        }
+    }
+    run.pre_generate_next_floor();
+    run.enter_next_floor();
+}
+
+fn count_player_units(run: &Run) -> usize {
+    run.units.iter().filter(|(u, _)| u.team == Team::Player).count()
+}
+
+// Plays a floor to the end, returns false if the run ended on it
+fn play_floor(run: &mut Run, options: &Options, stats: &mut FloorStats) -> bool {
+    stats.runs += 1;
+    let material = run.material;
+    let units = count_player_units(run);
+    let mut turns = 0;
+
+    let death = loop {
+        if turns >= options.max_turns {
+            break "turn limit".to_owned()
+        }
+        turns += 1;
+        // Stalemated player passes under classic rules
+        if run.player_turn {
+            let Some(m) = player_move(run, options) else {
+                break "no moves".to_owned()
+            };
+            run.make_player_move(&m);
+            if run.outcome == Some(Outcome::Won) || run.is_on_stairs() {
+                break String::new()
+            }
+        }
+
+        let m = ai_move(run, options);
+        run.apply_ai_move(m);
+        if run.outcome == Some(Outcome::Lost) {
+            let killer = run.unit_at(m.unwrap().to).unwrap();
+            break format!("{:?}", killer.unit_type)
+        }
+    };
+
+    stats.turns += turns;
+    // Buying in the shop happens after this, so material only goes up here
+    stats.material_earned += run.material - material;
+    stats.units_lost += (units - count_player_units(run)) as u32;
+    if death.is_empty() {
+        stats.cleared += 1;
+        true
+    }
+    else {
+        *stats.deaths.entry(death).or_default() += 1;
+        false
+    }
+}
+
+fn simulate(options: &Options) -> Vec<FloorStats> {
+    let mut stats = (0..LAST_FLOOR).map(|_| FloorStats::default()).collect::<Vec<_>>();
+    for seed in options.seed..options.seed + options.runs {
+        let mut run = Run::new(options.difficulty, options.engine, false, options.classic_rules, options.hit_points, seed);
+        run.replay = None;
+        if let Some(floor) = options.floor {
+            while run.floor < floor {
+                go_shopping(&mut run);
+            }
+        }
+
+        loop {
+            let floor = run.floor;
+            if !play_floor(&mut run, options, &mut stats[floor - 1]) {
+                break
+            }
+            if run.outcome == Some(Outcome::Won) || options.floor.is_some() {
+                break
+            }
+            go_shopping(&mut run);
+        }
+    }
+    stats
+}
+
+fn write_csv(stats: &[FloorStats], out: &mut impl std::io::Write) -> std::io::Result<()> {
+    writeln!(out, "floor,runs,cleared,win_rate,avg_turns,avg_material_earned,avg_units_lost,deaths")?;
+    for (i, floor) in stats.iter().enumerate().filter(|(_, s)| s.runs > 0) {
+        let runs = floor.runs as f32;
+        let deaths = floor.deaths.iter().map(|(cause, count)| format!("{cause}:{count}")).collect::<Vec<_>>().join(";");
+        writeln!(out, "{},{},{},{:.3},{:.1},{:.1},{:.2},{}",
+            i + 1, floor.runs, floor.cleared, floor.cleared as f32 / runs,
+            floor.turns as f32 / runs, floor.material_earned as f32 / runs, floor.units_lost as f32 / runs, deaths)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{USAGE}");
+        return
+    }
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let stats = simulate(&options);
+    write_csv(&stats, &mut std::io::stdout().lock()).expect("failed to write the statistics");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulate_floor_test() {
+        let args = ["--runs", "2", "--floor", "3", "--time-ms", "5", "--max-turns", "20"].map(String::from);
+        let options = parse_args(&args).unwrap();
+        let stats = simulate(&options);
+        assert_eq!(stats[2].runs, 2);
+        assert!(stats.iter().enumerate().all(|(i, s)| i == 2 || s.runs == 0));
+        assert_eq!(stats[2].cleared + stats[2].deaths.values().sum::<u32>(), 2);
+
+        let mut csv = vec![];
+        write_csv(&stats, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.lines().nth(1).unwrap().starts_with("3,2,"));
+
+        assert!(parse_args(&["--player".to_owned(), "Human".to_owned()]).is_err());
+    }
+}
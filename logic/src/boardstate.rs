@@ -0,0 +1,898 @@
+use std::sync::Arc;
+use glam::{IVec2, ivec2};
+use crate::{piece::{Jump, Movement}, tile::*, unit::*};
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: IVec2,
+    pub to: IVec2,
+    // Square of a unit captured by jumping over it, None when a unit on `to` is captured
+    pub capture: Option<IVec2>,
+    // Unit type the moving unit promotes into
+    pub promotion: Option<UnitType>,
+}
+
+impl Move {
+    // Square of the unit that is captured if there is one
+    pub fn capture_pos(&self) -> IVec2 {
+        self.capture.unwrap_or(self.to)
+    }
+}
+
+// What a square is for a unit of some team
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Square {
+    Blocked,
+    Empty,
+    Friend,
+    Enemy,
+}
+
+// Which moves visit_moves generates
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Generate {
+    All,
+    Captures,
+    // Squares where an enemy would be captured, whether there is one or not
+    Attacks,
+}
+
+// Why the team to move can't move under classic rules
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameEnd {
+    Checkmate,
+    Stalemate,
+}
+
+// What make_move changed, so that unmake_move can restore the board exactly
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    pub r#move: Move,
+    // Captured unit and its index in units
+    pub captured: Option<(usize, Unit)>,
+    // Unit that survived the capture and its index before it was hit, the moved unit stayed in place
+    pub hit: Option<(usize, Unit)>,
+    // Moved unit before the move
+    pub unit: Unit,
+    pub hash: u64,
+}
+
+// Where and into what units promote, set by the run for each floor
+#[derive(Default, Debug)]
+pub struct Promotions {
+    // Unit types the player can pick from and the material there is for paying them
+    pub player_types: Vec<UnitType>,
+    pub material: i32,
+    // Corners of the room the player started the floor in, AI units promote when they get there
+    pub ai_zone: Option<(IVec2, IVec2)>,
+}
+
+// Number of times each square is attacked by a team
+pub struct AttackMap {
+    counts: Vec<u32>,
+    width: usize,
+}
+
+impl AttackMap {
+    pub fn get(&self, pos: IVec2) -> u32 {
+        if pos.x < 0 || pos.y < 0 || pos.x as usize >= self.width {
+            return 0
+        }
+        self.counts.get(pos.x as usize + pos.y as usize * self.width).copied().unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (IVec2, u32)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, count)| (ivec2((i % self.width) as i32, (i / self.width) as i32), *count))
+    }
+}
+
+#[derive(Default)]
+pub struct BoardState {
+    pub tilemap: Arc<TileMap>,
+    // Changed only through the constructor and make_move so that the occupancy grid stays in sync
+    pub(crate) units: Vec<Unit>,
+    pub stairs: Option<IVec2>,
+    pub hash: u64,
+    // Index into units for every square of the tilemap
+    pub(crate) occupancy: Vec<Option<u16>>,
+    pub promotions: Arc<Promotions>,
+    // Material that player promotions have spent since the board was made, out of promotions.material
+    pub(crate) promotion_spent: i32,
+    // Moves that leave the king of the moving team capturable are not valid
+    pub classic_rules: bool,
+    // Units with armor or hp left take a hit instead of being captured
+    pub hit_points: bool,
+}
+
+pub fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// Zobrist key of a single unit. Keys are derived from the unit's fields instead of a table,
+// so boards of any size can be hashed.
+pub fn zobrist_key(unit: &Unit) -> u64 {
+    let (unit_type, jester_type) = (unit.unit_type.index() as u64, unit.jester_type.index() as u64);
+    // Unit types past the first 16 use the high bits, so that keys of the other units stay the same
+    let packed = (unit_type & 0xf)
+        | (jester_type & 0xf) << 4
+        | (unit.team as u64) << 8
+        | (unit.captures as u64) << 9
+        | (unit.damage as u64) << 11
+        | (unit.armor as u64) << 14
+        | (unit.pos.x as u16 as u64) << 16
+        | (unit.pos.y as u16 as u64) << 32
+        | (unit_type >> 4) << 48
+        | (jester_type >> 4) << 56;
+    splitmix64(packed)
+}
+
+impl BoardState {
+    pub fn new(tilemap: Arc<TileMap>, units: Vec<Unit>) -> BoardState {
+        let mut state = BoardState { stairs: tilemap.find_tile(Tile::Stairs), tilemap, units, ..Default::default() };
+        state.rebuild_indices();
+        state
+    }
+
+    pub fn shallow_clone(&self) -> BoardState {
+        BoardState {
+            tilemap: Arc::clone(&self.tilemap),
+            units: self.units.clone(),
+            stairs: self.stairs,
+            hash: self.hash,
+            occupancy: self.occupancy.clone(),
+            promotions: Arc::clone(&self.promotions),
+            promotion_spent: self.promotion_spent,
+            classic_rules: self.classic_rules,
+            hit_points: self.hit_points,
+        }
+    }
+
+    pub fn units(&self) -> &[Unit] {
+        &self.units
+    }
+
+    pub fn compute_hash(&self) -> u64 {
+        self.units.iter().fold(0, |hash, u| hash ^ zobrist_key(u))
+    }
+
+    // Recomputes the hash and the occupancy grid, must be called after units have been added to the board manually
+    pub(crate) fn rebuild_indices(&mut self) {
+        self.hash = self.compute_hash();
+        self.occupancy = vec![None; self.tilemap.get_width() * self.tilemap.get_height()];
+        for (index, unit) in self.units.iter().enumerate() {
+            let square = self.square(unit.pos).expect("unit is outside the tilemap");
+            self.occupancy[square] = Some(index as u16);
+        }
+    }
+
+    fn square(&self, pos: IVec2) -> Option<usize> {
+        self.tilemap.is_inside(pos).then(|| pos.x as usize + pos.y as usize * self.tilemap.get_width())
+    }
+
+    fn unit_index_at(&self, point: &IVec2) -> Option<usize> {
+        debug_assert_eq!(self.occupancy.len(), self.tilemap.get_width() * self.tilemap.get_height(), "rebuild_indices was not called");
+        self.occupancy[self.square(*point)?].map(|index| index as usize)
+    }
+
+    pub fn get_unit_at(&self, point: &IVec2) -> Option<&Unit> {
+        self.units.get(self.unit_index_at(point)?)
+    }
+
+    pub fn is_valid(&self, m: &Move) -> bool {
+        match self.get_unit_at(&m.from) {
+            Some(this_unit) => {
+                let other_unit_is_enemy = self.get_unit_at(&m.to).map(|u| is_enemy(this_unit, u));
+                self.tilemap.is_passable(m.to) && other_unit_is_enemy.unwrap_or(true)
+            }
+            None => {
+                false
+            }
+        }
+    }
+
+    fn square_for(&self, team: Team, pos: IVec2) -> Square {
+        if !self.tilemap.is_passable(pos) {
+            return Square::Blocked
+        }
+        match self.get_unit_at(&pos) {
+            Some(other_unit) if other_unit.team == team => Square::Friend,
+            Some(_) => Square::Enemy,
+            None => Square::Empty,
+        }
+    }
+
+    // Calls f for the moves of one movement in one direction
+    fn visit_direction(&self, unit: &Unit, movement: &Movement, dir: IVec2, generate: Generate, f: &mut impl FnMut(Move)) {
+        let mut emit = |m: Move, is_capture: bool| {
+            let wanted = match generate {
+                Generate::All => if is_capture { movement.captures } else { movement.moves },
+                Generate::Captures => is_capture && movement.captures,
+                Generate::Attacks => movement.captures,
+            };
+            if wanted {
+                f(m)
+            }
+        };
+        let step = |to: IVec2| Move { from: unit.pos, to, ..Default::default() };
+
+        let mut to = unit.pos;
+        let mut hopped = false;
+        for _ in 0..movement.range {
+            to += dir;
+            let square = self.square_for(unit.team, to);
+            match (movement.jump, square) {
+                (_, Square::Blocked) => break,
+                (Jump::Hop, Square::Empty) if !hopped => {}
+                (Jump::Grasshopper, Square::Empty) => {}
+                (Jump::Locust, Square::Empty) if generate != Generate::Attacks => {}
+                (Jump::None | Jump::Hop, Square::Empty) => emit(step(to), false),
+                (Jump::None, Square::Enemy) => {
+                    emit(step(to), true);
+                    break
+                }
+                (Jump::None, Square::Friend) => break,
+                (Jump::Hop, _) if !hopped => hopped = true,
+                (Jump::Hop, Square::Enemy) => {
+                    emit(step(to), true);
+                    break
+                }
+                (Jump::Hop, _) => break,
+                (Jump::Grasshopper, _) => {
+                    match self.square_for(unit.team, to + dir) {
+                        Square::Empty => emit(step(to + dir), false),
+                        Square::Enemy => emit(step(to + dir), true),
+                        _ => {}
+                    }
+                    break
+                }
+                (Jump::Locust, _) => {
+                    // An empty square is only passed here when looking for attacks
+                    if square != Square::Friend && self.square_for(unit.team, to + dir) == Square::Empty {
+                        emit(Move { from: unit.pos, to: to + dir, capture: Some(to), promotion: None }, true);
+                    }
+                    if square != Square::Empty {
+                        break
+                    }
+                }
+            }
+        }
+    }
+
+    // Calls f for every move of the unit moving in the given style, without allocating
+    fn visit_moves(&self, unit: &Unit, style: UnitType, generate: Generate, f: &mut impl FnMut(Move)) {
+        if style == UnitType::Jester {
+            assert!(unit.jester_type != UnitType::Jester);
+            return self.visit_moves(unit, unit.jester_type, generate, f)
+        }
+        let promotes = unit.can_promote() && generate != Generate::Attacks;
+        for movement in &style.def().moves {
+            for dir in &movement.directions {
+                if promotes {
+                    self.visit_direction(unit, movement, *dir, generate, &mut |m| self.visit_promotions(unit, m, f));
+                }
+                else {
+                    self.visit_direction(unit, movement, *dir, generate, f);
+                }
+            }
+        }
+    }
+
+    // Calls f for the move of a unit that can promote, with the promotions it can make
+    fn visit_promotions(&self, unit: &Unit, m: Move, f: &mut impl FnMut(Move)) {
+        let target = self.get_unit_at(&m.capture_pos());
+        // Attacker of a unit that survives the hit stays where it was
+        if matches!(target, Some(target) if self.survives_hit(target)) {
+            return f(m)
+        }
+        match unit.team {
+            // AI units promote automatically
+            Team::Ai => {
+                let in_zone = matches!(self.promotions.ai_zone, Some((min, max)) if m.to.cmpge(min).all() && m.to.cmple(max).all());
+                f(Move { promotion: if in_zone { unit.unit_type.def().promotion } else { None }, ..m })
+            }
+            Team::Player => {
+                f(m);
+                let captures = unit.captures + target.is_some() as u8;
+                if Some(m.to) == self.stairs || captures >= PROMOTION_CAPTURES {
+                    let promotions = &self.promotions;
+                    for &promotion in promotions.player_types.iter().filter(|t| **t != unit.unit_type) {
+                        if promotion_cost(unit.unit_type, promotion) <= promotions.material - self.promotion_spent {
+                            f(Move { promotion: Some(promotion), ..m });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Under classic rules drops the moves from start on that leave the king of the team capturable
+    fn retain_legal(&self, team: Team, moves: &mut Vec<Move>, start: usize) {
+        if !self.classic_rules || moves.len() == start {
+            return
+        }
+        let mut state = self.shallow_clone();
+        let mut index = 0;
+        moves.retain(|m| {
+            index += 1;
+            if index <= start {
+                return true
+            }
+            let undo = state.make_move(m);
+            let legal = !state.in_check(team);
+            state.unmake_move(&undo);
+            legal
+        });
+    }
+
+    // Appends the moves of the unit to the buffer
+    pub fn generate_moves_for_unit(&self, unit: &Unit, captures_only: bool, moves: &mut Vec<Move>) {
+        let start = moves.len();
+        let generate = if captures_only { Generate::Captures } else { Generate::All };
+        self.visit_moves(unit, unit.unit_type, generate, &mut |m| moves.push(m));
+        self.retain_legal(unit.team, moves, start);
+    }
+
+    // Appends the moves of the team to the buffer
+    pub fn generate_moves(&self, team: Team, captures_only: bool, moves: &mut Vec<Move>) {
+        let start = moves.len();
+        let generate = if captures_only { Generate::Captures } else { Generate::All };
+        for unit in self.units.iter().filter(|u| u.team == team) {
+            self.visit_moves(unit, unit.unit_type, generate, &mut |m| moves.push(m));
+        }
+        self.retain_legal(team, moves, start);
+    }
+
+    pub fn has_moves(&self, team: Team) -> bool {
+        if self.classic_rules {
+            return !self.get_valid_moves(team).is_empty()
+        }
+        self.units.iter().filter(|u| u.team == team).any(|unit| {
+            let mut found = false;
+            self.visit_moves(unit, unit.unit_type, Generate::All, &mut |_| found = true);
+            found
+        })
+    }
+
+    pub fn get_valid_moves_for_unit(&self, unit: &Unit) -> Vec<Move> {
+        let mut moves = vec![];
+        self.generate_moves_for_unit(unit, false, &mut moves);
+        moves
+    }
+
+    pub fn get_valid_moves(&self, team: Team) -> Vec<Move> {
+        let mut moves = vec![];
+        self.generate_moves(team, false, &mut moves);
+        moves
+    }
+
+    // Moves that would capture if there was an enemy on their capture_pos, including squares
+    // occupied by the unit's own team
+    pub fn get_attacks_for_unit(&self, unit: &Unit) -> Vec<Move> {
+        let flipped = BoardState {
+            tilemap: Arc::clone(&self.tilemap),
+            units: self.units
+                .iter()
+                .map(|u| if u.team == unit.team && u.pos != unit.pos { Unit { team: unit.team.opponent(), ..*u } } else { *u })
+                .collect(),
+            stairs: self.stairs,
+            hash: 0,
+            occupancy: self.occupancy.clone(),
+            promotions: Arc::clone(&self.promotions),
+            promotion_spent: self.promotion_spent,
+            classic_rules: self.classic_rules,
+            hit_points: self.hit_points,
+        };
+        let mut moves = vec![];
+        flipped.visit_moves(unit, unit.unit_type, Generate::Attacks, &mut |m| moves.push(m));
+        moves
+    }
+
+    pub fn attack_map(&self, team: Team) -> AttackMap {
+        let width = self.tilemap.get_width();
+        let mut counts = vec![0; width * self.tilemap.get_height()];
+        for unit in self.units.iter().filter(|u| u.team == team) {
+            for m in self.get_attacks_for_unit(unit) {
+                let pos = m.capture_pos();
+                counts[pos.x as usize + pos.y as usize * width] += 1;
+            }
+        }
+        AttackMap { counts, width }
+    }
+
+    // Units of the team that are attacked more times than they are defended
+    pub fn units_en_prise(&self, team: Team) -> Vec<Unit> {
+        let attacks = self.attack_map(team.opponent());
+        let defences = self.attack_map(team);
+        self.units
+            .iter()
+            .filter(|u| u.team == team && attacks.get(u.pos) > defences.get(u.pos))
+            .copied()
+            .collect()
+    }
+
+    pub fn is_on_stairs(&self) -> bool {
+        if self.stairs.is_some() {
+            self.units
+                .iter()
+                .any(|u| u.team == Team::Player && u.pos == self.stairs.unwrap())
+        }
+        else {
+            false
+        }
+    }
+
+    pub fn is_end(&self) -> bool {
+        !self.has_moves(Team::Player) || !self.has_moves(Team::Ai)
+    }
+
+    fn survives_hit(&self, unit: &Unit) -> bool {
+        self.hit_points && unit.survives_hit()
+    }
+
+    // Whether the opponent could capture the king of the team with its next move, a king that would
+    // only take a hit is not in check
+    pub fn in_check(&self, team: Team) -> bool {
+        let Some(king) = self.units.iter().find(|u| u.team == team && u.unit_type == UnitType::King) else {
+            return false
+        };
+        if self.survives_hit(king) {
+            return false
+        }
+        self.units.iter().filter(|u| u.team != team).any(|unit| {
+            let mut attacks_king = false;
+            self.visit_moves(unit, unit.unit_type, Generate::Captures, &mut |m| attacks_king |= m.capture_pos() == king.pos);
+            attacks_king
+        })
+    }
+
+    // None while the team to move has valid moves or when the rules are not classic
+    pub fn game_end(&self, team: Team) -> Option<GameEnd> {
+        if !self.classic_rules || self.has_moves(team) {
+            None
+        }
+        else if self.in_check(team) {
+            Some(GameEnd::Checkmate)
+        }
+        else {
+            Some(GameEnd::Stalemate)
+        }
+    }
+
+    pub fn make_move(&mut self, m: &Move) -> Undo {
+        let (from, to) = (self.square(m.from).unwrap(), self.square(m.to).unwrap());
+        let hash_before = self.hash;
+
+        // A unit that survives the capture takes a hit and the attacker bounces back
+        let capture = self.square(m.capture_pos()).unwrap();
+        if let Some(index) = self.occupancy[capture] {
+            let target = &mut self.units[index as usize];
+            if self.hit_points && target.survives_hit() {
+                let target_before = *target;
+                self.hash ^= zobrist_key(target);
+                target.take_hit();
+                self.hash ^= zobrist_key(target);
+                let unit = self.units[self.occupancy[from].unwrap() as usize];
+                return Undo { r#move: *m, captured: None, hit: Some((index as usize, target_before)), unit, hash: hash_before }
+            }
+        }
+
+        // Delete the captured unit, which is at the target position unless it was jumped over
+        let mut captured = None;
+        let mut captured_unit = None;
+        if let Some(index) = self.occupancy[capture].take() {
+            let index = index as usize;
+            captured = Some((index, self.units[index]));
+            captured_unit = Some(self.units[index]);
+            self.hash ^= zobrist_key(&self.units[index]);
+            self.units.swap_remove(index);
+            // The last unit took the place of the captured one
+            if let Some(moved) = self.units.get(index) {
+                let square = self.square(moved.pos).unwrap();
+                self.occupancy[square] = Some(index as u16);
+            }
+        }
+        // Move unit to target position
+        let index = self.occupancy[from].take().unwrap();
+        self.occupancy[to] = Some(index);
+        let unit = &mut self.units[index as usize];
+        let unit_before = *unit;
+        let mut hash = zobrist_key(unit);
+        unit.pos = m.to;
+
+        if captured_unit.is_some() && unit.can_promote() {
+            unit.captures = (unit.captures + 1).min(PROMOTION_CAPTURES);
+        }
+        // Jester transformation
+        if unit.unit_type == UnitType::Jester {
+            if let Some(captured_unit) = captured_unit {
+                unit.convert_jester(captured_unit);
+            }
+        }
+        if let Some(promotion) = m.promotion {
+            if unit.team == Team::Player {
+                self.promotion_spent += promotion_cost(unit.unit_type, promotion);
+            }
+            unit.unit_type = promotion;
+            unit.captures = 0;
+        }
+        hash ^= zobrist_key(unit);
+        self.hash ^= hash;
+
+        Undo { r#move: *m, captured, hit: None, unit: unit_before, hash: hash_before }
+    }
+
+    pub fn unmake_move(&mut self, undo: &Undo) {
+        let m = undo.r#move;
+        let (from, to) = (self.square(m.from).unwrap(), self.square(m.to).unwrap());
+        let capture = self.square(m.capture_pos()).unwrap();
+
+        if let Some((index, hit_unit)) = undo.hit {
+            self.units[index] = hit_unit;
+            self.hash = undo.hash;
+            return
+        }
+
+        // Move unit back
+        let index = self.occupancy[to].take().unwrap();
+        self.occupancy[from] = Some(index);
+        self.units[index as usize] = undo.unit;
+        if let Some(promotion) = m.promotion {
+            if undo.unit.team == Team::Player {
+                self.promotion_spent -= promotion_cost(undo.unit.unit_type, promotion);
+            }
+        }
+
+        // Put the captured unit back to its index, reversing swap_remove
+        if let Some((index, captured_unit)) = undo.captured {
+            self.units.push(captured_unit);
+            let last = self.units.len() - 1;
+            self.units.swap(index, last);
+            let square = self.square(self.units[last].pos).unwrap();
+            self.occupancy[square] = Some(last as u16);
+            self.occupancy[capture] = Some(index as u16);
+        }
+        self.hash = undo.hash;
+    }
+
+    // Number of move sequences of the given length with teams taking turns, for testing move generation
+    pub fn perft(&mut self, team: Team, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1
+        }
+        let moves = self.get_valid_moves(team);
+        if depth == 1 {
+            return moves.len() as u64
+        }
+        moves
+            .iter()
+            .map(|m| {
+                let undo = self.make_move(m);
+                let count = self.perft(team.opponent(), depth - 1);
+                self.unmake_move(&undo);
+                count
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::position::Position;
+    use super::*;
+
+    fn make_board(map_plan: &[&str], units: &[(i32, i32, UnitType, Team)]) -> BoardState {
+        let mut state = BoardState {
+            tilemap: Arc::new(TileMap::from(map_plan)),
+            ..Default::default()
+        };
+        for &(x, y, unit_type, team) in units {
+            state.units.push(Unit { pos: ivec2(x, y), unit_type, team, ..Default::default() });
+        }
+        state.rebuild_indices();
+        state
+    }
+
+    #[test]
+    fn attack_map_test() {
+        let map_plan = [
+        ".....",
+        "..#..",
+        "....."];
+        let state = make_board(&map_plan, &[
+            (0, 0, UnitType::Rook, Team::Ai),
+            (2, 0, UnitType::Pawn, Team::Ai),
+            (4, 2, UnitType::Knight, Team::Ai),
+            (0, 2, UnitType::King, Team::Player),
+        ]);
+        let attacks = state.attack_map(Team::Ai);
+
+        // Rook defends its own pawn but does not see through it
+        assert_eq!(attacks.get(ivec2(1, 0)), 2);
+        assert_eq!(attacks.get(ivec2(2, 0)), 1);
+        // Square behind the pawn is attacked by the pawn and the knight
+        assert_eq!(attacks.get(ivec2(3, 0)), 2);
+        // Rook attacks the king
+        assert_eq!(attacks.get(ivec2(0, 2)), 1);
+        // Walls are never attacked, outside positions are zero
+        assert_eq!(attacks.get(ivec2(2, 1)), 0);
+        assert_eq!(attacks.get(ivec2(-1, 0)), 0);
+        assert_eq!(attacks.iter().map(|(_, count)| count).sum::<u32>(), 7);
+    }
+
+    #[test]
+    fn en_prise_test() {
+        let map_plan = [
+        ".....",
+        ".....",
+        "....."];
+        let state = make_board(&map_plan, &[
+            (4, 0, UnitType::Rook, Team::Ai),
+            (0, 0, UnitType::Knight, Team::Player),
+            (2, 2, UnitType::Pawn, Team::Player),
+            (4, 2, UnitType::Pawn, Team::Player),
+            (3, 2, UnitType::Pawn, Team::Player),
+        ]);
+        let en_prise = state.units_en_prise(Team::Player);
+        assert_eq!(en_prise.len(), 1);
+        assert_eq!(en_prise[0].pos, ivec2(0, 0));
+    }
+
+    // Leaf counts for depths 1 to 4. Changing any of these means move generation has changed.
+    const PERFT_POSITIONS: [(&str, [u64; 4]); 11] = [
+        // Sliders stop after 20 squares
+        ("R.......................k p", [20, 20, 457, 878]),
+        ("R.....................r./........................ p", [21, 291, 4706, 79037]),
+        // Jesters move like the piece they last captured and keep the style of captured jesters
+        ("J(n)...j/..#../..b../...../K...q p", [4, 82, 571, 11636]),
+        ("J...j/...../....J(b) a", [6, 34, 142, 921]),
+        // Archbishop and queen combine two move styles
+        ("......./.A...q./......./..#..../K.....k p", [12, 224, 2902, 49028]),
+        // Captures on the stairs
+        ("K(<)...r/..#../...../N...b a", [7, 32, 210, 1439]),
+        (".r(<)../.R../.... p", [5, 20, 90, 397]),
+        ("..<../.R.r./..... p", [5, 22, 111, 583]),
+        // Hoppers and locusts capture units that are not on the target square
+        ("C.p.r/.g.../..D../d...G/....R a", [10, 116, 1230, 15783]),
+        ("K.d.c/.D.../..#D./g..../....C p", [14, 97, 1199, 8874]),
+        // Floor 1 of seed 1
+        ("________......./________......./________.BKN.../________......./________......./_________..._.p/\
+          _________....../_________..p.../_________...___/_________....../_________..p.../_________....../\
+          ____________.../____________.._/____________.<_ p", [20, 220, 4168, 44648]),
+    ];
+
+    // Leaf counts with hit points on, units with armor or hp left take a hit and the attacker stays
+    // where it was. Queens and archbishops have 2 hp.
+    const HIT_POINTS_PERFT_POSITIONS: [(&str, [u64; 4]); 3] = [
+        ("J(n)...j/..#../..b../...../K...q p", [4, 82, 571, 11721]),
+        ("......./.A...q./......./..#..../K.....k p", [12, 224, 2941, 50599]),
+        ("Q(+)..r(+)/.N../..k(++)./.... a", [14, 134, 1496, 14813]),
+    ];
+
+    // Positions of both tables with the rules they are counted under
+    fn perft_positions() -> impl Iterator<Item = (&'static str, [u64; 4], Position)> {
+        let with_rules = |hit_points| move |(text, counts): (&'static str, [u64; 4])| {
+            let mut position = text.parse::<Position>().unwrap();
+            position.state.hit_points = hit_points;
+            (text, counts, position)
+        };
+        PERFT_POSITIONS.into_iter().map(with_rules(false)).chain(HIT_POINTS_PERFT_POSITIONS.into_iter().map(with_rules(true)))
+    }
+
+    fn assert_same_board(a: &BoardState, b: &BoardState, context: &str) {
+        assert_eq!(a.units, b.units, "{context}");
+        assert_eq!(a.occupancy, b.occupancy, "{context}");
+        assert_eq!(a.hash, b.hash, "{context}");
+    }
+
+    #[test]
+    fn unmake_move_test() {
+        for (text, _, Position { mut state, .. }) in perft_positions() {
+            let original = state.shallow_clone();
+            for team in [Team::Player, Team::Ai] {
+                for m in state.get_valid_moves(team) {
+                    let undo = state.make_move(&m);
+                    assert_eq!(state.hash, state.compute_hash());
+                    let after_move = state.shallow_clone();
+
+                    // Also check replies, so that boards with a changed unit order are covered
+                    for reply in state.get_valid_moves(team.opponent()) {
+                        let reply_undo = state.make_move(&reply);
+                        state.unmake_move(&reply_undo);
+                        assert_same_board(&state, &after_move, &format!("{reply:?} after {m:?} in {text}"));
+                    }
+
+                    state.unmake_move(&undo);
+                    assert_same_board(&state, &original, &format!("{m:?} in {text}"));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn capture_generation_test() {
+        for (text, _, Position { state, .. }) in perft_positions() {
+            for team in [Team::Player, Team::Ai] {
+                let mut moves = state.get_valid_moves(team);
+                assert_eq!(state.has_moves(team), !moves.is_empty());
+
+                let mut captures = vec![];
+                state.generate_moves(team, true, &mut captures);
+                moves.retain(|m| state.get_unit_at(&m.capture_pos()).is_some());
+                assert_eq!(captures, moves, "{text}");
+            }
+        }
+    }
+
+    #[test]
+    fn jump_capture_test() {
+        let moves = |text: &str| {
+            let state = text.parse::<Position>().unwrap().state;
+            let mut moves = state.get_valid_moves_for_unit(&state.units[0]);
+            moves.sort_by_key(|m| (m.to.y, m.to.x));
+            moves.iter().map(|m| (m.to, m.capture)).collect::<Vec<_>>()
+        };
+        // Cannon moves like a rook but captures only over a screen
+        assert_eq!(moves("C.p.r/...../..... p"), [(ivec2(1, 0), None), (ivec2(4, 0), None), (ivec2(0, 1), None), (ivec2(0, 2), None)]);
+        // Grasshopper lands right behind the first unit in each direction
+        assert_eq!(moves("G.p../...../..r../...n. p"), [(ivec2(3, 0), None), (ivec2(3, 3), None)]);
+        // Checker captures the enemy it jumps over, but only when it can land behind it
+        assert_eq!(moves("D.../.p../.... p"), [(ivec2(2, 2), Some(ivec2(1, 1)))]);
+        assert_eq!(moves("D.../.p../..P. p"), []);
+        assert_eq!(moves("D.../.P../.... p"), []);
+
+        let mut state = "D.../.p../.... p".parse::<Position>().unwrap().state;
+        let undo = state.make_move(&Move { from: ivec2(0, 0), to: ivec2(2, 2), capture: Some(ivec2(1, 1)), promotion: None });
+        assert_eq!(state.units.len(), 1);
+        assert_eq!(state.get_unit_at(&ivec2(1, 1)), None);
+        assert_eq!(state.get_unit_at(&ivec2(2, 2)).unwrap().unit_type, "Checker".parse().unwrap());
+        state.unmake_move(&undo);
+        assert_eq!(state.get_unit_at(&ivec2(1, 1)).unwrap().team, Team::Ai);
+
+        // Only squares where something would be captured are attacked
+        let attacked = |text: &str| {
+            let state = text.parse::<Position>().unwrap().state;
+            let attacks = state.attack_map(Team::Player);
+            attacks.iter().map(|(pos, _)| pos).collect::<Vec<_>>()
+        };
+        assert_eq!(attacked("C.p.r/...../..... p"), [ivec2(3, 0), ivec2(4, 0)]);
+        assert_eq!(attacked("G.p../...../..r../...n. p"), [ivec2(3, 0), ivec2(3, 3)]);
+        assert_eq!(attacked("D.../..../.... p"), [ivec2(1, 1)]);
+    }
+
+    #[test]
+    fn hit_test() {
+        let mut state = "R.q(-+)/... p".parse::<Position>().unwrap().state;
+        let units = state.units.clone();
+        let m = Move { from: ivec2(0, 0), to: ivec2(2, 0), ..Default::default() };
+
+        // Without hit points every capture takes the unit
+        let undo = state.make_move(&m);
+        assert_eq!(state.units.len(), 1);
+        state.unmake_move(&undo);
+
+        state.hit_points = true;
+
+        // Armor takes the first hit and the rook bounces back
+        let first = state.make_move(&m);
+        let queen = *state.get_unit_at(&ivec2(2, 0)).unwrap();
+        assert_eq!((queen.armor, queen.hp()), (0, 1));
+        assert_eq!(state.get_unit_at(&ivec2(0, 0)).unwrap().unit_type, UnitType::Rook);
+        assert_eq!(state.hash, state.compute_hash());
+
+        // Last hit point is lost with the capture
+        let second = state.make_move(&m);
+        assert_eq!(state.units.len(), 1);
+        assert_eq!(state.get_unit_at(&ivec2(2, 0)).unwrap().unit_type, UnitType::Rook);
+
+        state.unmake_move(&second);
+        state.unmake_move(&first);
+        assert_eq!(state.units, units);
+        assert_eq!(state.hash, state.compute_hash());
+    }
+
+    #[test]
+    fn promotion_test() {
+        let mut state = "P(2)<../r..p/.... p".parse::<Position>().unwrap().state;
+        state.promotions = Arc::new(Promotions {
+            player_types: vec![UnitType::Pawn, UnitType::Knight, UnitType::Queen],
+            material: 3,
+            ai_zone: Some((ivec2(2, 1), ivec2(3, 2))),
+        });
+        let moves = |state: &BoardState, index: usize| {
+            let mut moves = state.get_valid_moves_for_unit(&state.units[index]);
+            moves.sort_by_key(|m| (m.to.y, m.to.x, m.promotion.map(|t| t.index())));
+            moves.iter().map(|m| (m.to, m.promotion)).collect::<Vec<_>>()
+        };
+        // Player pawn can promote into what it can afford on the stairs and on its third capture
+        let knight = Some(UnitType::Knight);
+        assert_eq!(moves(&state, 0), [(ivec2(1, 0), None), (ivec2(1, 0), knight), (ivec2(0, 1), None), (ivec2(0, 1), knight)]);
+        // AI pawn promotes automatically in the start room of the player
+        let queen = Some(UnitType::Queen);
+        assert_eq!(moves(&state, 2), [(ivec2(3, 0), None), (ivec2(2, 1), queen), (ivec2(3, 2), queen)]);
+
+        let hash = state.hash;
+        let undo = state.make_move(&Move { from: ivec2(0, 0), to: ivec2(0, 1), capture: None, promotion: knight });
+        let unit = state.get_unit_at(&ivec2(0, 1)).unwrap();
+        assert_eq!((unit.unit_type, unit.captures), (UnitType::Knight, 0));
+        state.unmake_move(&undo);
+        assert_eq!(state.get_unit_at(&ivec2(0, 0)).unwrap().captures, 2);
+        assert_eq!(state.get_unit_at(&ivec2(0, 1)).unwrap().unit_type, UnitType::Rook);
+        assert_eq!(state.hash, hash);
+
+        // Without promoting the capture is counted
+        state.make_move(&Move { from: ivec2(0, 0), to: ivec2(0, 1), ..Default::default() });
+        assert_eq!(state.get_unit_at(&ivec2(0, 1)).unwrap().captures, PROMOTION_CAPTURES);
+
+        // Material spent on a promotion earlier in the line can't pay for another one
+        let mut state = "P(2)r/P(2)r p".parse::<Position>().unwrap().state;
+        state.promotions = Arc::new(Promotions { player_types: vec![UnitType::Knight], material: 3, ai_zone: None });
+        let promotions = |state: &BoardState| {
+            let moves = state.get_valid_moves_for_unit(state.get_unit_at(&ivec2(0, 1)).unwrap());
+            moves.iter().filter(|m| m.promotion.is_some()).count()
+        };
+        assert_eq!(promotions(&state), 1);
+        let undo = state.make_move(&Move { from: ivec2(0, 0), to: ivec2(1, 0), capture: None, promotion: knight });
+        assert_eq!(promotions(&state), 0);
+        state.unmake_move(&undo);
+        assert_eq!(promotions(&state), 1);
+    }
+
+    #[test]
+    fn classic_rules_test() {
+        let state = |text: &str| {
+            let mut state = text.parse::<Position>().unwrap().state;
+            state.classic_rules = true;
+            state
+        };
+        let king_moves = |state: &BoardState| {
+            let mut moves = state.get_valid_moves(Team::Player).iter().map(|m| m.to).collect::<Vec<_>>();
+            moves.sort_by_key(|p| (p.y, p.x));
+            moves
+        };
+
+        let check = state("K.r/.../... p");
+        assert!(check.in_check(Team::Player));
+        assert!(!check.in_check(Team::Ai));
+        // King can't step next to the rook or stay on its line
+        assert_eq!(king_moves(&check), [ivec2(0, 1), ivec2(1, 1)]);
+        assert_eq!(check.game_end(Team::Player), None);
+        let mut free = state("K.r/.../... p");
+        free.classic_rules = false;
+        assert_eq!(free.get_valid_moves(Team::Player).len(), 3);
+
+        let checkmate = state("K.r/..r/... p");
+        assert_eq!(king_moves(&checkmate), []);
+        assert_eq!(checkmate.game_end(Team::Player), Some(GameEnd::Checkmate));
+
+        let mut stalemate = state("K../..r/.r. p");
+        assert!(!stalemate.in_check(Team::Player));
+        assert!(!stalemate.has_moves(Team::Player));
+        assert_eq!(stalemate.game_end(Team::Player), Some(GameEnd::Stalemate));
+        stalemate.classic_rules = false;
+        assert_eq!(stalemate.game_end(Team::Player), None);
+
+        // Armor only counts with hit points on, then the king would just take a hit
+        let mut armored = state("K(+).r/..r/... p");
+        assert_eq!(armored.game_end(Team::Player), Some(GameEnd::Checkmate));
+        armored.hit_points = true;
+        assert!(!armored.in_check(Team::Player));
+        assert_eq!(king_moves(&armored), [ivec2(1, 0), ivec2(0, 1), ivec2(1, 1)]);
+        assert_eq!(armored.game_end(Team::Player), None);
+    }
+
+    #[test]
+    fn perft_test() {
+        for (text, counts, mut position) in perft_positions() {
+            for (depth, count) in (1..).zip(counts) {
+                assert_eq!(position.state.perft(position.side_to_move, depth), count, "depth {depth} of {text}");
+            }
+        }
+    }
+}
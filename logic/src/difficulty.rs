@@ -0,0 +1,33 @@
+use enum_iterator::Sequence;
+
+use crate::evaluation::SearchSettings;
+
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash, Sequence)]
+pub enum Difficulty {
+    Easy,
+    #[default] Normal,
+    Hard,
+    Brutal,
+}
+
+impl Difficulty {
+    pub fn search_settings(self, seed: u64) -> SearchSettings {
+        let (time_budget_ms, max_depth, eval_noise) = match self {
+            Difficulty::Easy => (100.0, 2, 30.0),
+            Difficulty::Normal => (500.0, 5, 5.0),
+            Difficulty::Hard => (1000.0, 8, 0.0),
+            Difficulty::Brutal => (2000.0, 12, 0.0),
+        };
+        SearchSettings { time_budget_ms, max_depth, eval_noise, seed }
+    }
+
+    // Chance that the AI deliberately plays a random move instead of the best one
+    pub fn suboptimal_move_chance(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.25,
+            Difficulty::Normal => 0.05,
+            Difficulty::Hard => 0.0,
+            Difficulty::Brutal => 0.0,
+        }
+    }
+}
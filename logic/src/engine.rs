@@ -0,0 +1,34 @@
+use std::sync::{Arc, atomic::AtomicBool};
+use enum_iterator::Sequence;
+
+use crate::{boardstate::BoardState, evaluation::{Evaluation, SearchResult, SearchSettings}, mcts::MctsEngine, unit::Team};
+
+// Something that picks a move for a team
+pub trait Engine {
+    fn search(&mut self, state: BoardState, team: Team, settings: &SearchSettings, cancel: Arc<AtomicBool>) -> SearchResult;
+}
+
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash, Sequence)]
+pub enum EngineKind {
+    #[default] Minimax,
+    Mcts,
+}
+
+impl EngineKind {
+    pub fn create(self) -> Box<dyn Engine> {
+        match self {
+            EngineKind::Minimax => Box::new(MinimaxEngine),
+            EngineKind::Mcts => Box::new(MctsEngine),
+        }
+    }
+}
+
+// Alpha-beta search with iterative deepening
+pub struct MinimaxEngine;
+
+impl Engine for MinimaxEngine {
+    fn search(&mut self, state: BoardState, team: Team, settings: &SearchSettings, cancel: Arc<AtomicBool>) -> SearchResult {
+        let mut eval = Evaluation::from_gamestate(state);
+        eval.iterative_deepening(team == Team::Player, settings, cancel)
+    }
+}
@@ -0,0 +1,670 @@
+use std::{collections::HashMap, cmp::Reverse, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{SystemTime, UNIX_EPOCH}};
+use once_cell::sync::OnceCell;
+
+use crate::{unit::*, boardstate::{BoardState, GameEnd, Move, splitmix64}, utils, transposition::*};
+
+// Values past this are decided by a lost king or the stairs. Their depth bonus depends on the depth
+// left when the game ended, so like mate scores they are stored relative to the node.
+const DECIDED_VALUE: f32 = 50_000.0;
+
+fn value_to_tt(value: f32, depth: u32) -> f32 {
+    if value > DECIDED_VALUE { value - depth as f32 } else if value < -DECIDED_VALUE { value + depth as f32 } else { value }
+}
+
+fn value_from_tt(value: f32, depth: u32) -> f32 {
+    if value > DECIDED_VALUE { value + depth as f32 } else if value < -DECIDED_VALUE { value - depth as f32 } else { value }
+}
+
+pub struct Evaluation {
+    pub state: BoardState,
+}
+
+// How often (in nodes) the search checks the clock, must be a power of two
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+static CLOCK: OnceCell<fn() -> f64> = OnceCell::new();
+
+// Replaces the clock used for search deadlines, for platforms where std has no clock
+pub fn set_clock(clock: fn() -> f64) {
+    let _ = CLOCK.set(clock);
+}
+
+pub fn now_ms() -> f64 {
+    match CLOCK.get() {
+        Some(clock) => clock(),
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64() * 1000.0),
+    }
+}
+
+const MAX_QUIESCENCE_DEPTH: u32 = 6;
+
+// A point of armor is worth a bit less than a pawn
+const ARMOR_VALUE: f32 = 5.0;
+
+const TT_MOVE_SCORE: i32 = 3_000_000;
+const CAPTURE_SCORE: i32 = 2_000_000;
+const KILLER_SCORE: i32 = 1_000_000;
+
+// State shared by all nodes of one search
+pub struct SearchContext {
+    pub tt: TranspositionTable,
+    pub deadline_ms: Option<f64>,
+    pub cancel: Arc<AtomicBool>,
+    pub aborted: bool,
+    pub nodes: u64,
+    pub move_ordering: bool,
+    // Random amount added to leaf evaluations, derived from the position so it is stable within a search
+    pub eval_noise: f32,
+    pub noise_seed: u64,
+    // Maximum number of captures searched past the horizon
+    pub quiescence_depth: u32,
+    // Two quiet moves per ply from the root that caused a cutoff
+    killers: Vec<[Option<Move>; 2]>,
+    history: HashMap<(Team, Move), i32>,
+    // Distance from the root of the node being searched
+    ply: usize,
+    // Move lists are reused between nodes to avoid allocating
+    move_buffers: Vec<Vec<Move>>,
+}
+
+impl Default for SearchContext {
+    fn default() -> Self {
+        SearchContext {
+            tt: Default::default(),
+            deadline_ms: None,
+            cancel: Default::default(),
+            aborted: false,
+            nodes: 0,
+            move_ordering: true,
+            eval_noise: 0.0,
+            noise_seed: 0,
+            quiescence_depth: MAX_QUIESCENCE_DEPTH,
+            killers: vec![],
+            history: HashMap::new(),
+            ply: 0,
+            move_buffers: vec![],
+        }
+    }
+}
+
+impl SearchContext {
+    fn take_move_buffer(&mut self) -> Vec<Move> {
+        let mut moves = self.move_buffers.pop().unwrap_or_default();
+        moves.clear();
+        moves
+    }
+
+    fn is_killer(&self, m: &Move) -> bool {
+        self.killers.get(self.ply).map(|k| k.contains(&Some(*m))).unwrap_or(false)
+    }
+
+    // Remembers a quiet move that caused a beta cutoff
+    fn add_cutoff(&mut self, depth: u32, team: Team, m: &Move) {
+        if self.killers.len() <= self.ply {
+            self.killers.resize(self.ply + 1, [None; 2]);
+        }
+        let killers = &mut self.killers[self.ply];
+        if killers[0] != Some(*m) {
+            killers[1] = killers[0];
+            killers[0] = Some(*m);
+        }
+        *self.history.entry((team, *m)).or_insert(0) += (depth * depth) as i32;
+    }
+
+    fn should_stop(&mut self) -> bool {
+        self.nodes += 1;
+        if !self.aborted && self.nodes & (DEADLINE_CHECK_INTERVAL - 1) == 0 {
+            let timeout = self.deadline_ms.map(|deadline| now_ms() >= deadline).unwrap_or(false);
+            self.aborted = timeout || self.cancel.load(Ordering::Relaxed);
+        }
+        self.aborted
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchSettings {
+    pub time_budget_ms: f64,
+    pub max_depth: u32,
+    pub eval_noise: f32,
+    // Seed for the random parts of the search
+    pub seed: u64,
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub value: f32,
+    pub depth: u32,
+}
+impl Evaluation {
+    pub fn from_gamestate(state: BoardState) -> Evaluation {
+        Evaluation {
+            state,
+        }
+    }
+
+    // Captures first by most valuable victim / least valuable attacker, then killer moves and
+    // finally other quiet moves by their history score
+    fn order_moves(&self, moves: &mut [Move], team: Team, tt_move: Option<Move>, search: &SearchContext) {
+        moves.sort_by_cached_key(|m| {
+            let score =
+                if Some(*m) == tt_move {
+                    TT_MOVE_SCORE
+                }
+                else if let Some(victim) = self.state.get_unit_at(&m.capture_pos()) {
+                    let attacker = self.state.get_unit_at(&m.from).unwrap();
+                    CAPTURE_SCORE + 100 * material_reward(victim.unit_type) - material_reward(attacker.unit_type)
+                }
+                else if search.is_killer(m) {
+                    KILLER_SCORE
+                }
+                else {
+                    search.history.get(&(team, *m)).copied().unwrap_or(0)
+                };
+            Reverse(score)
+        });
+    }
+
+    pub fn evaluate(&self) -> f32 {
+        fn unit_value(unit: &Unit) -> f32 {
+            let multiplier = if unit.team == Team::Ai { -1.0 } else { 1.0 };
+            let def = unit.unit_type.def();
+            // Damaged units lose up to half of their value
+            let health = 0.5 + 0.5 * unit.hp() as f32 / def.hp as f32;
+            multiplier * (def.value * health + ARMOR_VALUE * unit.armor as f32)
+        }
+
+        let player_close_to_stairs: f32 = if let Some(stairs) = self.state.stairs {
+            self.state.units
+            .iter()
+            .filter(|u| u.team == Team::Player)
+            .map(|u| 20.0 / (utils::dist(&stairs, &u.pos).max(1.0)))
+            .sum()
+        }
+        else {
+            0.0
+        };
+
+        // TODO closeness to player king
+        let king_pos = self.state.units
+            .iter()
+            .find(|u| u.team == Team::Player && u.unit_type == UnitType::King)
+            .map(|u| u.pos);
+
+        let closeness =
+            if let Some(king_pos) = king_pos {
+                self.state.units
+                    .iter()
+                    .filter(|u| u.team == Team::Ai)
+                    .map(|u| utils::dist(&king_pos, &u.pos))
+                    .sum()
+            }
+            else {
+                0.0
+            };
+
+        let stairs = if self.state.is_on_stairs() { 1000000.0 } else { 0.0 };
+
+        let fake_enemy_king = unit_value(&Unit { unit_type: UnitType::King, team: Team::Ai, ..Default::default() });
+        fake_enemy_king + self.state.units.iter().map(unit_value).sum::<f32>() + player_close_to_stairs + closeness + stairs
+    }
+
+    fn leaf_eval(&self, depth: u32, maximizing_player: bool, search: &SearchContext) -> f32 {
+        let noise =
+            if search.eval_noise > 0.0 {
+                let random = splitmix64(self.state.hash ^ search.noise_seed) as f64 / u64::MAX as f64;
+                search.eval_noise * (2.0 * random as f32 - 1.0)
+            }
+            else {
+                0.0
+            };
+        self.evaluate() + noise + if maximizing_player { 10.0 - depth as f32 } else { depth as f32 - 10.0 }
+    }
+
+    // Searches only captures past the horizon so that pieces are not left hanging on the last ply.
+    // The side to move may always stand pat instead of capturing.
+    fn quiescence(&mut self, alpha_param: f32, beta_param: f32, maximizing_player: bool, depth: u32, search: &mut SearchContext) -> f32 {
+        let stand_pat = self.leaf_eval(0, maximizing_player, search);
+        // Game is decided on the stairs and without a king, captures after that don't count
+        if search.should_stop() || depth == 0 || self.state.is_on_stairs() || self.state.is_end() {
+            return stand_pat
+        }
+
+        let mut alpha = alpha_param;
+        let mut beta = beta_param;
+        if (maximizing_player && stand_pat >= beta) || (!maximizing_player && stand_pat <= alpha) {
+            return stand_pat
+        }
+
+        let current_team = if maximizing_player { Team::Player } else { Team::Ai };
+        let mut captures = search.take_move_buffer();
+        self.state.generate_moves(current_team, true, &mut captures);
+        self.order_moves(&mut captures, current_team, None, search);
+
+        let value = if maximizing_player {
+            let mut max_eval = stand_pat;
+            alpha = alpha.max(max_eval);
+            for r#move in captures.iter() {
+                let undo = self.state.make_move(r#move);
+                let current_eval = self.quiescence(alpha, beta, false, depth - 1, search);
+                self.state.unmake_move(&undo);
+                if search.aborted {
+                    break
+                }
+                max_eval = max_eval.max(current_eval);
+                if max_eval >= beta {
+                    break
+                }
+                alpha = alpha.max(max_eval);
+            }
+            max_eval
+        }
+        else {
+            let mut min_eval = stand_pat;
+            beta = beta.min(min_eval);
+            for r#move in captures.iter() {
+                let undo = self.state.make_move(r#move);
+                let current_eval = self.quiescence(alpha, beta, true, depth - 1, search);
+                self.state.unmake_move(&undo);
+                if search.aborted {
+                    break
+                }
+                min_eval = min_eval.min(current_eval);
+                if min_eval <= alpha {
+                    break
+                }
+                beta = beta.min(min_eval);
+            }
+            min_eval
+        };
+        search.move_buffers.push(captures);
+        value
+    }
+
+    pub fn minimax(&mut self, depth: u32, alpha_param: f32, beta_param: f32, maximizing_player: bool, search: &mut SearchContext, debug: &mut Vec<(Move, f32)>) -> (Option<Move>, f32) {
+        if search.should_stop() {
+            return (None, 0.0)
+        }
+
+        let current_team = if maximizing_player { Team::Player } else { Team::Ai };
+        if self.state.classic_rules {
+            match self.state.game_end(current_team) {
+                // Checkmate is scored as if the king was captured
+                Some(GameEnd::Checkmate) => {
+                    let king_value = UnitType::King.def().value;
+                    return (None, self.leaf_eval(depth, maximizing_player, search) + if maximizing_player { -king_value } else { king_value })
+                }
+                Some(GameEnd::Stalemate) => return (None, self.leaf_eval(depth, maximizing_player, search)),
+                None => {}
+            }
+        }
+        else if self.state.is_end() {
+            return (None, self.leaf_eval(depth, maximizing_player, search))
+        }
+
+        if depth == 0 {
+            return (None, self.quiescence(alpha_param, beta_param, maximizing_player, search.quiescence_depth, search))
+        }
+
+        let key = self.state.hash ^ if maximizing_player { PLAYER_TO_MOVE_KEY } else { 0 };
+        let mut alpha = alpha_param;
+        let mut beta = beta_param;
+
+        let mut tt_move = None;
+
+        if let Some(entry) = search.tt.probe(key) {
+            if entry.depth >= depth {
+                let value = value_from_tt(entry.value, depth);
+                match entry.bound {
+                    Bound::Exact => return (entry.best_move, value),
+                    Bound::Lower => alpha = alpha.max(value),
+                    Bound::Upper => beta = beta.min(value),
+                }
+                if alpha >= beta {
+                    return (entry.best_move, value)
+                }
+            }
+            tt_move = entry.best_move;
+        }
+
+        let mut moves = search.take_move_buffer();
+        self.state.generate_moves(current_team, false, &mut moves);
+        if search.move_ordering {
+            self.order_moves(&mut moves, current_team, tt_move, search);
+        }
+        else if let Some(index) = tt_move.and_then(|m| moves.iter().position(|x| *x == m)) {
+            moves.swap(0, index);
+        }
+
+        let (alpha_orig, beta_orig) = (alpha, beta);
+        let mut best_move: Option<Move> = moves.first().copied();
+        let mut aborted_eval = None;
+
+        let best_eval = if maximizing_player {
+            let mut max_eval = f32::MIN;
+            for r#move in moves.iter() {
+                let undo = self.state.make_move(r#move);
+                search.ply += 1;
+                let current_eval = self.minimax(depth - 1, alpha, beta, false, search, &mut vec![]).1;
+                search.ply -= 1;
+                self.state.unmake_move(&undo);
+                if search.aborted {
+                    aborted_eval = Some(current_eval);
+                    break
+                }
+
+                if current_eval > max_eval {
+                    max_eval = current_eval;
+                    best_move = Some(*r#move);
+                }
+                if max_eval >= beta {
+                    if self.state.get_unit_at(&r#move.capture_pos()).is_none() {
+                        search.add_cutoff(depth, current_team, r#move);
+                    }
+                    break
+                }
+                if max_eval > alpha {
+                    alpha = max_eval
+                }
+                debug.push((*r#move, current_eval));
+            }
+            max_eval
+        }
+        else {
+            let mut min_eval = f32::MAX;
+            for r#move in moves.iter() {
+                let undo = self.state.make_move(r#move);
+                search.ply += 1;
+                let current_eval = self.minimax(depth - 1, alpha, beta, true, search, &mut vec![]).1;
+                search.ply -= 1;
+                self.state.unmake_move(&undo);
+                if search.aborted {
+                    aborted_eval = Some(current_eval);
+                    break
+                }
+
+                if current_eval < min_eval {
+                    min_eval = current_eval;
+                    best_move = Some(*r#move);
+                }
+                if min_eval <= alpha {
+                    if self.state.get_unit_at(&r#move.capture_pos()).is_none() {
+                        search.add_cutoff(depth, current_team, r#move);
+                    }
+                    break
+                }
+                if min_eval < beta {
+                    beta = min_eval;
+                }
+                debug.push((*r#move, current_eval));
+            }
+            min_eval
+        };
+        search.move_buffers.push(moves);
+        if let Some(value) = aborted_eval {
+            return (best_move, value)
+        }
+
+        let bound =
+            if best_eval <= alpha_orig {
+                Bound::Upper
+            }
+            else if best_eval >= beta_orig {
+                Bound::Lower
+            }
+            else {
+                Bound::Exact
+            };
+        search.tt.store(Entry { key, depth, value: value_to_tt(best_eval, depth), bound, best_move });
+
+        (best_move, best_eval)
+    }
+
+    // Searches depth 1, 2, 3... until the time budget runs out and returns the result of the deepest
+    // fully searched depth. Depth 1 is always searched to completion unless the search is cancelled.
+    pub fn iterative_deepening(&mut self, maximizing_player: bool, settings: &SearchSettings, cancel: Arc<AtomicBool>) -> SearchResult {
+        let deadline = now_ms() + settings.time_budget_ms;
+        let mut search = SearchContext {
+            cancel,
+            eval_noise: settings.eval_noise,
+            noise_seed: settings.seed,
+            ..Default::default()
+        };
+        let mut result = SearchResult::default();
+
+        for depth in 1..=settings.max_depth.max(1) {
+            search.deadline_ms = if depth == 1 { None } else { Some(deadline) };
+            let (best_move, value) = self.minimax(depth, f32::MIN, f32::MAX, maximizing_player, &mut search, &mut vec![]);
+            if search.aborted {
+                break
+            }
+            result = SearchResult { best_move, value, depth };
+            if best_move.is_none() || now_ms() >= deadline {
+                break
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use glam::ivec2;
+    use super::*;
+
+    #[test]
+    fn test() {
+        use crate::tile::TileMap;
+        let map_plan = [
+        "..#####",
+        "##..##.",
+        "##...#.",
+        ".......",
+        "##...##",
+        "##...#.",
+        "##...#."];
+
+        let mut gamestate = BoardState {
+            tilemap: Arc::new(TileMap::from(&map_plan[..])),
+            ..Default::default()
+        };
+        gamestate.units.push(Unit { pos: ivec2(3, 2), unit_type: UnitType::Pawn, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(2, 3), unit_type: UnitType::Knight, team: Team::Ai, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(3, 3), unit_type: UnitType::Knight, team: Team::Ai, ..Default::default() });
+
+        gamestate.rebuild_indices();
+        let eval = Evaluation::from_gamestate(gamestate);
+
+        let mut eval2 = Evaluation::from_gamestate(eval.state.shallow_clone());
+        eval2.state.make_move(&Move { from: ivec2(3, 2), to: ivec2(3, 3), ..Default::default() });
+
+        assert_eq!(eval.state.units.first().unwrap().pos, ivec2(3, 2));
+        assert_eq!(eval2.state.units.first().unwrap().pos, ivec2(3, 3));
+
+        assert_eq!(eval.state.units.len(), 3);
+        assert_eq!(eval2.state.units.len(), 2);
+
+        assert_eq!(eval.state.hash, eval.state.compute_hash());
+        assert_eq!(eval2.state.hash, eval2.state.compute_hash());
+        assert_ne!(eval.state.hash, eval2.state.hash);
+    }
+
+    #[test]
+    fn transposition_table_test() {
+        use crate::tile::TileMap;
+        let map_plan = [
+        ".......",
+        ".......",
+        ".......",
+        ".......",
+        "......."];
+
+        let mut gamestate = BoardState {
+            tilemap: Arc::new(TileMap::from(&map_plan[..])),
+            ..Default::default()
+        };
+        gamestate.units.push(Unit { pos: ivec2(0, 0), unit_type: UnitType::King, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(3, 2), unit_type: UnitType::Jester, jester_type: UnitType::Rook, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(6, 4), unit_type: UnitType::Knight, team: Team::Ai, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(5, 0), unit_type: UnitType::Pawn, team: Team::Ai, ..Default::default() });
+        gamestate.rebuild_indices();
+
+        let mut eval = Evaluation::from_gamestate(gamestate);
+        let single_slot = eval.minimax(3, f32::MIN, f32::MAX, false, &mut SearchContext { tt: TranspositionTable::new(1), ..Default::default() }, &mut vec![]).1;
+        let full_size = eval.minimax(3, f32::MIN, f32::MAX, false, &mut SearchContext::default(), &mut vec![]).1;
+        assert_eq!(single_slot, full_size);
+
+        // Same position reached through a different move order has the same hash
+        let mut a = eval.state.shallow_clone();
+        a.make_move(&Move { from: ivec2(0, 0), to: ivec2(1, 0), ..Default::default() });
+        a.make_move(&Move { from: ivec2(3, 2), to: ivec2(3, 4), ..Default::default() });
+        let mut b = eval.state.shallow_clone();
+        b.make_move(&Move { from: ivec2(3, 2), to: ivec2(3, 4), ..Default::default() });
+        b.make_move(&Move { from: ivec2(0, 0), to: ivec2(1, 0), ..Default::default() });
+        assert_eq!(a.hash, b.hash);
+    }
+
+    #[test]
+    fn decided_value_tt_test() {
+        // Line that ended with 3 plies left below a node searched to depth 5 ends with 2 left at depth 4
+        assert_eq!(value_from_tt(value_to_tt(1_000_003.0, 5), 4), 1_000_002.0);
+        assert_eq!(value_from_tt(value_to_tt(-100_003.0, 5), 4), -100_002.0);
+        assert_eq!(value_from_tt(value_to_tt(42.0, 5), 4), 42.0);
+    }
+
+    #[test]
+    fn iterative_deepening_test() {
+        use crate::tile::TileMap;
+        let map_plan = [
+        ".....",
+        ".....",
+        "....."];
+
+        let mut gamestate = BoardState {
+            tilemap: Arc::new(TileMap::from(&map_plan[..])),
+            ..Default::default()
+        };
+        gamestate.units.push(Unit { pos: ivec2(0, 0), unit_type: UnitType::King, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(4, 0), unit_type: UnitType::Rook, team: Team::Ai, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(4, 2), unit_type: UnitType::Pawn, team: Team::Ai, ..Default::default() });
+        gamestate.rebuild_indices();
+
+        let mut eval = Evaluation::from_gamestate(gamestate);
+        let result = eval.iterative_deepening(false, &SearchSettings { time_budget_ms: 10_000.0, max_depth: 4, eval_noise: 0.0, seed: 0 }, Default::default());
+        assert_eq!(result.depth, 4);
+        // Enemy rook takes the king
+        assert_eq!(result.best_move, Some(Move { from: ivec2(4, 0), to: ivec2(0, 0), ..Default::default() }));
+
+        // No time at all still searches depth 1
+        let result = eval.iterative_deepening(false, &SearchSettings { time_budget_ms: 0.0, max_depth: 10, eval_noise: 0.0, seed: 0 }, Default::default());
+        assert!(result.depth >= 1);
+        assert!(result.best_move.is_some());
+    }
+
+    #[test]
+    fn move_ordering_test() {
+        use crate::tile::TileMap;
+        let map_plan = [
+        "########",
+        "#......#",
+        "#......#",
+        "#..##..#",
+        "#......#",
+        "#......#",
+        "########"];
+
+        let mut gamestate = BoardState {
+            tilemap: Arc::new(TileMap::from(&map_plan[..])),
+            ..Default::default()
+        };
+        gamestate.units.push(Unit { pos: ivec2(1, 5), unit_type: UnitType::King, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(2, 5), unit_type: UnitType::Knight, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(3, 5), unit_type: UnitType::Bishop, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(1, 4), unit_type: UnitType::Rook, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(6, 1), unit_type: UnitType::Rook, team: Team::Ai, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(5, 2), unit_type: UnitType::Bishop, team: Team::Ai, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(3, 1), unit_type: UnitType::Knight, team: Team::Ai, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(4, 4), unit_type: UnitType::Pawn, team: Team::Ai, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(6, 5), unit_type: UnitType::Pawn, team: Team::Ai, ..Default::default() });
+        gamestate.rebuild_indices();
+
+        let mut eval = Evaluation::from_gamestate(gamestate);
+
+        let mut unordered = SearchContext { move_ordering: false, ..Default::default() };
+        let mut ordered = SearchContext::default();
+        for depth in 1..=4 {
+            eval.minimax(depth, f32::MIN, f32::MAX, false, &mut unordered, &mut vec![]);
+            eval.minimax(depth, f32::MIN, f32::MAX, false, &mut ordered, &mut vec![]);
+        }
+        assert!(ordered.nodes < unordered.nodes, "ordered {} >= unordered {}", ordered.nodes, unordered.nodes);
+    }
+
+    #[test]
+    fn quiescence_test() {
+        use crate::tile::TileMap;
+        let map_plan = [
+        ".......",
+        ".......",
+        ".......",
+        ".......",
+        ".......",
+        ".......",
+        "......."];
+
+        // Enemy rook can take a pawn that is defended by the player's rook
+        let mut gamestate = BoardState {
+            tilemap: Arc::new(TileMap::from(&map_plan[..])),
+            ..Default::default()
+        };
+        gamestate.units.push(Unit { pos: ivec2(0, 6), unit_type: UnitType::King, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(3, 3), unit_type: UnitType::Pawn, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(3, 6), unit_type: UnitType::Rook, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(6, 3), unit_type: UnitType::Rook, team: Team::Ai, ..Default::default() });
+        gamestate.rebuild_indices();
+        let mut eval = Evaluation::from_gamestate(gamestate);
+        let grab_pawn = Move { from: ivec2(6, 3), to: ivec2(3, 3), ..Default::default() };
+
+        let mut horizon = SearchContext { quiescence_depth: 0, ..Default::default() };
+        let blunder = eval.minimax(1, f32::MIN, f32::MAX, false, &mut horizon, &mut vec![]).0;
+        assert_eq!(blunder, Some(grab_pawn));
+
+        let best_move = eval.minimax(1, f32::MIN, f32::MAX, false, &mut SearchContext::default(), &mut vec![]).0;
+        assert_ne!(best_move, Some(grab_pawn));
+
+        // Player bishop can take a pawn that is defended by a rook
+        let mut gamestate = BoardState {
+            tilemap: Arc::new(TileMap::from(&map_plan[..])),
+            ..Default::default()
+        };
+        gamestate.units.push(Unit { pos: ivec2(0, 6), unit_type: UnitType::King, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(2, 4), unit_type: UnitType::Bishop, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(4, 2), unit_type: UnitType::Pawn, team: Team::Ai, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(4, 1), unit_type: UnitType::Rook, team: Team::Ai, ..Default::default() });
+        gamestate.rebuild_indices();
+        let mut eval = Evaluation::from_gamestate(gamestate);
+        let grab_pawn = Move { from: ivec2(2, 4), to: ivec2(4, 2), ..Default::default() };
+
+        let mut horizon = SearchContext { quiescence_depth: 0, ..Default::default() };
+        let blunder = eval.minimax(1, f32::MIN, f32::MAX, true, &mut horizon, &mut vec![]).0;
+        assert_eq!(blunder, Some(grab_pawn));
+
+        let best_move = eval.minimax(1, f32::MIN, f32::MAX, true, &mut SearchContext::default(), &mut vec![]).0;
+        assert_ne!(best_move, Some(grab_pawn));
+
+        // Player rook on the stairs has already won, the enemy rook can't take it back
+        let mut gamestate = BoardState {
+            tilemap: Arc::new(TileMap::from(&["<...", "...."][..])),
+            stairs: Some(ivec2(0, 0)),
+            ..Default::default()
+        };
+        gamestate.units.push(Unit { pos: ivec2(0, 0), unit_type: UnitType::Rook, team: Team::Player, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(3, 0), unit_type: UnitType::Rook, team: Team::Ai, ..Default::default() });
+        gamestate.units.push(Unit { pos: ivec2(0, 1), unit_type: UnitType::King, team: Team::Player, ..Default::default() });
+        gamestate.rebuild_indices();
+        let mut eval = Evaluation::from_gamestate(gamestate);
+        let mut search = SearchContext::default();
+        let stand_pat = eval.leaf_eval(0, false, &search);
+        assert_eq!(eval.quiescence(f32::MIN, f32::MAX, false, MAX_QUIESCENCE_DEPTH, &mut search), stand_pat);
+    }
+}
@@ -0,0 +1,17 @@
+// Rules, map generation, AI and run progression of King's Conquest without any rendering
+pub mod unit;
+pub mod piece;
+pub mod tile;
+pub mod evaluation;
+pub mod utils;
+pub mod boardstate;
+pub mod mapgenerator;
+pub mod transposition;
+pub mod aiworker;
+pub mod difficulty;
+pub mod engine;
+pub mod mcts;
+pub mod savegame;
+pub mod position;
+pub mod replay;
+pub mod run;
@@ -1,8 +1,14 @@
-use macroquad::prelude::{Rect, IVec2, ivec2};
+use glam::{IVec2, ivec2};
 use rand::{rngs::SmallRng, Rng};
 
 use crate::{tile::{TileMap, Tile}, utils};
 
+// Rectangle of tiles
+struct Rect {
+    pos: IVec2,
+    size: IVec2,
+}
+
 struct RectIterator {
     width: i32,
     height: i32,
@@ -28,9 +34,9 @@ impl Iterator for RectIterator {
 
 fn iterate_rect(rect: &Rect) -> RectIterator {
     RectIterator {
-        width: rect.w as i32,
-        height: rect.h as i32,
-        offset: rect.point().as_ivec2(),
+        width: rect.size.x,
+        height: rect.size.y,
+        offset: rect.pos,
         pos: ivec2(-1, 0),
     }
 }
@@ -41,14 +47,8 @@ fn gen_ivec2(rng: &mut SmallRng, min: i32, max: i32) -> IVec2 {
     ivec2(x, y)
 }
 
-fn gen_centered_rect(rng: &mut SmallRng, center: &IVec2, min: i32, max: i32) -> Rect {
-    let size = gen_ivec2(rng, min, max);
-    let offset = (size.as_vec2() * 0.5).as_ivec2();
-    Rect::new((center.x - offset.x) as f32, (center.y - offset.y) as f32, size.x as f32, size.y as f32)
-}
-
 fn make_rect(pos: &IVec2, size: &IVec2) -> Rect {
-    Rect::new(pos.x as f32, pos.y as f32, size.x as f32, size.y as f32)
+    Rect { pos: *pos, size: *size }
 }
 
 pub struct MapGenerator {
@@ -60,8 +60,12 @@ pub struct MapGenerator {
 pub struct MapGeneratorResult {
     pub tilemap: TileMap,
     pub start_pos: IVec2,
+    // Corners of the room around the start position
+    pub start_room: (IVec2, IVec2),
 }
 
+const START_ROOM_SIZE: i32 = 5;
+
 impl MapGenerator {
     pub fn new(rng: SmallRng, width: usize, height: usize) -> Self {
         MapGenerator {
@@ -98,7 +102,7 @@ impl MapGenerator {
     }
 
     fn try_generate(&mut self) -> bool {
-        let size = ivec2(5, 5);
+        let size = IVec2::splat(START_ROOM_SIZE);
         self.start_pos = ivec2(self.rng.gen_range(0..=(self.tilemap.get_width() as i32 - size.x)), self.rng.gen_range(0..=(self.tilemap.get_height() as i32 - size.y)));
 
         let start_room = make_rect(&self.start_pos, &size);
@@ -141,7 +145,7 @@ impl MapGenerator {
         potential_stairs.sort_by_cached_key(|p| utils::dist2(p, &self.start_pos) as i32);
 
         self.tilemap.set(*potential_stairs.last().unwrap(), Tile::Stairs);
-        self.start_pos += ivec2(2, 2);
+        self.start_pos += size / 2;
         true
     }
 
@@ -152,6 +156,7 @@ impl MapGenerator {
                 return MapGeneratorResult {
                     tilemap: self.tilemap.clone(),
                     start_pos: self.start_pos,
+                    start_room: (self.start_pos - IVec2::splat(START_ROOM_SIZE / 2), self.start_pos + IVec2::splat(START_ROOM_SIZE / 2)),
                 };
             }
         }
@@ -0,0 +1,191 @@
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{boardstate::{BoardState, GameEnd, Move}, engine::Engine, evaluation::{Evaluation, SearchResult, SearchSettings, now_ms}, unit::*};
+
+const EXPLORATION: f32 = 1.4;
+const PLAYOUT_DEPTH: u32 = 16;
+const MAX_ITERATIONS: u32 = 200_000;
+// Chance that a playout takes the most valuable capture instead of a random move
+const CAPTURE_CHANCE: f64 = 0.75;
+// Evaluation difference that maps to roughly 73% win chance
+const EVAL_SCALE: f32 = 50.0;
+
+struct Node {
+    r#move: Option<Move>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<Move>,
+    // Team to move in this node
+    team: Team,
+    depth: u32,
+    visits: u32,
+    // Sum of rewards for the team that made the move leading to this node
+    reward: f32,
+}
+
+// Monte Carlo tree search with lightly guided random playouts
+pub struct MctsEngine;
+
+fn kings(state: &BoardState) -> (bool, bool) {
+    let has_king = |team| state.units.iter().any(|u| u.team == team && u.unit_type == UnitType::King);
+    (has_king(Team::Player), has_king(Team::Ai))
+}
+
+// Player's chance of winning estimated from the evaluation
+fn player_reward(state: &BoardState) -> f32 {
+    let eval = Evaluation::from_gamestate(state.shallow_clone()).evaluate();
+    1.0 / (1.0 + (-eval / EVAL_SCALE).exp())
+}
+
+struct Search<'a> {
+    root_kings: (bool, bool),
+    rng: &'a mut SmallRng,
+}
+
+impl Search<'_> {
+    fn is_terminal(&self, state: &BoardState) -> bool {
+        state.is_on_stairs() || kings(state) != self.root_kings
+    }
+
+    fn playout(&mut self, state: &mut BoardState, mut team: Team) -> f32 {
+        let mut moves = vec![];
+        for _ in 0..PLAYOUT_DEPTH {
+            if self.is_terminal(state) {
+                break
+            }
+            moves.clear();
+            state.generate_moves(team, false, &mut moves);
+            if moves.is_empty() {
+                if state.game_end(team) == Some(GameEnd::Checkmate) {
+                    return if team == Team::Player { 0.0 } else { 1.0 }
+                }
+                break
+            }
+            let best_capture = moves
+                .iter()
+                .filter_map(|m| state.get_unit_at(&m.capture_pos()).map(|u| (m, material_reward(u.unit_type))))
+                .max_by_key(|(_, reward)| *reward)
+                .map(|(m, _)| *m);
+            let m = match best_capture {
+                Some(m) if self.rng.gen_bool(CAPTURE_CHANCE) => m,
+                _ => moves[self.rng.gen_range(0..moves.len())],
+            };
+            state.make_move(&m);
+            team = team.opponent();
+        }
+        player_reward(state)
+    }
+}
+
+impl Engine for MctsEngine {
+    fn search(&mut self, state: BoardState, team: Team, settings: &SearchSettings, cancel: Arc<AtomicBool>) -> SearchResult {
+        let deadline = now_ms() + settings.time_budget_ms;
+        let mut rng = SmallRng::seed_from_u64(settings.seed);
+        let mut search = Search { root_kings: kings(&state), rng: &mut rng };
+
+        let mut nodes = vec![Node {
+            r#move: None,
+            parent: None,
+            children: vec![],
+            untried: state.get_valid_moves(team),
+            team,
+            depth: 0,
+            visits: 0,
+            reward: 0.0,
+        }];
+        let mut max_depth = 0;
+
+        for iteration in 0..MAX_ITERATIONS {
+            // Always do some iterations so that there is a move to return
+            if iteration >= 100 && (now_ms() >= deadline || cancel.load(Ordering::Relaxed)) {
+                break
+            }
+
+            let mut current = state.shallow_clone();
+            let mut index = 0;
+
+            // Selection
+            while nodes[index].untried.is_empty() && !nodes[index].children.is_empty() {
+                let log_visits = (nodes[index].visits as f32).ln();
+                index = *nodes[index].children
+                    .iter()
+                    .max_by(|a, b| {
+                        let uct = |n: &Node| n.reward / n.visits as f32 + EXPLORATION * (log_visits / n.visits as f32).sqrt();
+                        uct(&nodes[**a]).total_cmp(&uct(&nodes[**b]))
+                    })
+                    .unwrap();
+                current.make_move(&nodes[index].r#move.unwrap());
+            }
+
+            // Expansion
+            if !nodes[index].untried.is_empty() {
+                let untried = &mut nodes[index].untried;
+                let m = untried.swap_remove(search.rng.gen_range(0..untried.len()));
+                current.make_move(&m);
+
+                let child_team = nodes[index].team.opponent();
+                let untried = if search.is_terminal(&current) { vec![] } else { current.get_valid_moves(child_team) };
+                let depth = nodes[index].depth + 1;
+                max_depth = max_depth.max(depth);
+                nodes.push(Node {
+                    r#move: Some(m),
+                    parent: Some(index),
+                    children: vec![],
+                    untried,
+                    team: child_team,
+                    depth,
+                    visits: 0,
+                    reward: 0.0,
+                });
+                let child = nodes.len() - 1;
+                nodes[index].children.push(child);
+                index = child;
+            }
+
+            // Simulation
+            let team_to_move = nodes[index].team;
+            let reward = search.playout(&mut current, team_to_move);
+
+            // Backpropagation
+            let mut node = Some(index);
+            while let Some(i) = node {
+                nodes[i].visits += 1;
+                nodes[i].reward += if nodes[i].team.opponent() == Team::Player { reward } else { 1.0 - reward };
+                node = nodes[i].parent;
+            }
+        }
+
+        nodes[0].children
+            .iter()
+            .map(|&i| &nodes[i])
+            .max_by_key(|n| n.visits)
+            .map(|n| SearchResult { best_move: n.r#move, value: n.reward / n.visits as f32, depth: max_depth })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::ivec2;
+    use crate::tile::TileMap;
+    use super::*;
+
+    #[test]
+    fn mcts_takes_king_test() {
+        let map_plan = [".....", ".....", "....."];
+        let mut state = BoardState {
+            tilemap: Arc::new(TileMap::from(&map_plan[..])),
+            ..Default::default()
+        };
+        state.units.push(Unit { pos: ivec2(0, 0), unit_type: UnitType::King, team: Team::Player, ..Default::default() });
+        state.units.push(Unit { pos: ivec2(1, 2), unit_type: UnitType::Pawn, team: Team::Player, ..Default::default() });
+        state.units.push(Unit { pos: ivec2(4, 0), unit_type: UnitType::Rook, team: Team::Ai, ..Default::default() });
+        state.units.push(Unit { pos: ivec2(4, 2), unit_type: UnitType::Pawn, team: Team::Ai, ..Default::default() });
+        state.rebuild_indices();
+
+        let settings = SearchSettings { time_budget_ms: 100.0, max_depth: 0, eval_noise: 0.0, seed: 1 };
+        let result = MctsEngine.search(state, Team::Ai, &settings, Default::default());
+        assert_eq!(result.best_move, Some(Move { from: ivec2(4, 0), to: ivec2(0, 0), ..Default::default() }));
+    }
+}
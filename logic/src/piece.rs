@@ -0,0 +1,322 @@
+// Piece definitions loaded from pieces.txt.
+//
+// Moves are written in Betza notation. A piece moves with a combination of atoms:
+//   W (1, 0)  F (1, 1)  D (2, 0)  N (2, 1)  A (2, 2)  H (3, 0)  C (3, 1)  Z (3, 2)  G (3, 3)
+//   K = WF, R = WW, B = FF, Q = BR
+// An atom leaps in every direction once, except R, B and Q that ride any number of squares in the
+// same direction until they are blocked. Doubling a leaping atom makes it a rider too (NN is the
+// nightrider) and a number after an atom limits how far it rides (W3 moves up to three squares).
+//
+// Lowercase modifiers before an atom change how it moves and captures:
+//   m  only moves to empty squares
+//   c  only captures
+//   p  hops over one unit of either team and then continues like a rider, like the cannon in cR
+//   g  hops over one unit and lands right after it, like the grasshopper gQ
+//   l  captures the enemy it jumps over and lands on the empty square beyond it, like a checker lF
+// A cannon that moves like a rook but captures by hopping is mRcpR. The range limits all squares
+// travelled, except that g and l always land right behind the unit they jump over.
+use std::{collections::HashMap, ops::RangeInclusive};
+use glam::{IVec2, ivec2};
+use once_cell::sync::Lazy;
+
+use crate::{savegame::{parse_bool, parse_number}, unit::UnitType};
+
+// Riders stop after this many squares
+pub const MAX_RANGE: usize = 20;
+
+// Hit points are stored in three bits of the zobrist keys
+pub const MAX_HP: u8 = 8;
+
+pub static PIECES: Lazy<Vec<PieceDef>> = Lazy::new(|| {
+    parse_pieces(include_str!("../pieces.txt")).unwrap_or_else(|e| panic!("pieces.txt: {e}"))
+});
+
+// How a movement passes over other units
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Jump {
+    #[default] None,
+    Hop,
+    Grasshopper,
+    Locust,
+}
+
+// A leap that is repeated up to range times in the same direction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movement {
+    pub directions: Vec<IVec2>,
+    pub range: usize,
+    // Whether the movement can end on an empty square and whether it can capture
+    pub moves: bool,
+    pub captures: bool,
+    pub jump: Jump,
+}
+
+pub struct PieceDef {
+    pub name: String,
+    pub letter: char,
+    pub sprite: String,
+    pub moves: Vec<Movement>,
+    pub price: i32,
+    pub reward: i32,
+    pub value: f32,
+    pub hp: u8,
+    pub shop: bool,
+    pub enemy_floors: Option<RangeInclusive<usize>>,
+    // Piece that AI units of this kind promote into, the player can pick any affordable piece
+    pub promotion: Option<UnitType>,
+    pub description: String,
+}
+
+// Directions of a leap in the order moves are generated: forward, backward and then sideways
+fn leap_directions(x: i32, y: i32) -> Vec<IVec2> {
+    let mut directions = vec![];
+    for (a, b) in [(x, y), (y, x)] {
+        for (sign_x, sign_y) in [(-1, -1), (-1, 1), (1, -1), (1, 1)] {
+            let dir = ivec2(a * sign_x, b * sign_y);
+            if !directions.contains(&dir) {
+                directions.push(dir);
+            }
+        }
+    }
+    directions.sort_by_key(|d| (match d.y.signum() { -1 => 0, 1 => 1, _ => 2 }, d.x));
+    directions
+}
+
+pub fn parse_betza(notation: &str) -> Result<Vec<Movement>, String> {
+    let mut movements: Vec<Movement> = vec![];
+    let mut chars = notation.chars().peekable();
+    while let Some(mut chr) = chars.next() {
+        let (mut moves, mut captures, mut jump) = (true, true, Jump::None);
+        while chr.is_ascii_lowercase() {
+            match chr {
+                'm' if moves && captures => captures = false,
+                'c' if moves && captures => moves = false,
+                'p' if jump == Jump::None => jump = Jump::Hop,
+                'g' if jump == Jump::None => jump = Jump::Grasshopper,
+                'l' if jump == Jump::None => jump = Jump::Locust,
+                'm' | 'c' | 'p' | 'g' | 'l' => return Err(format!("modifiers of '{notation}' contradict each other")),
+                _ => return Err(format!("unknown modifier '{chr}' in '{notation}'")),
+            }
+            chr = chars.next().ok_or_else(|| format!("'{notation}' ends without an atom"))?;
+        }
+        if jump == Jump::Locust {
+            if !captures {
+                return Err(format!("modifiers of '{notation}' contradict each other"));
+            }
+            // A locust only ever captures
+            moves = false;
+        }
+
+        let (leaps, mut range): (&[(i32, i32)], usize) = match chr {
+            'W' => (&[(1, 0)], 1),
+            'F' => (&[(1, 1)], 1),
+            'D' => (&[(2, 0)], 1),
+            'N' => (&[(2, 1)], 1),
+            'A' => (&[(2, 2)], 1),
+            'H' => (&[(3, 0)], 1),
+            'C' => (&[(3, 1)], 1),
+            'Z' => (&[(3, 2)], 1),
+            'G' => (&[(3, 3)], 1),
+            'K' => (&[(1, 0), (1, 1)], 1),
+            'R' => (&[(1, 0)], MAX_RANGE),
+            'B' => (&[(1, 1)], MAX_RANGE),
+            'Q' => (&[(1, 1), (1, 0)], MAX_RANGE),
+            _ => return Err(format!("unknown atom '{chr}' in '{notation}'")),
+        };
+        if range == 1 && leaps.len() == 1 && chars.next_if_eq(&chr).is_some() {
+            range = MAX_RANGE;
+        }
+        let mut digits = String::new();
+        while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+            digits.push(digit);
+        }
+        if !digits.is_empty() {
+            range = parse_number(&digits)?;
+            if range == 0 || range > MAX_RANGE {
+                return Err(format!("range of '{chr}' in '{notation}' must be between 1 and {MAX_RANGE}"));
+            }
+        }
+
+        for &(x, y) in leaps {
+            let directions = leap_directions(x, y);
+            let overlaps = |m: &Movement| m.jump == jump && (m.moves && moves || m.captures && captures);
+            if movements.iter().any(|m| m.directions.contains(&directions[0]) && overlaps(m)) {
+                return Err(format!("'{notation}' has the same leap more than once"));
+            }
+            movements.push(Movement { directions, range, moves, captures, jump });
+        }
+    }
+    if movements.is_empty() {
+        return Err("moves are empty".to_owned());
+    }
+    Ok(movements)
+}
+
+fn parse_floors(s: &str) -> Result<RangeInclusive<usize>, String> {
+    let (first, last) = s.split_once('-').unwrap_or((s, s));
+    Ok(parse_number(first)?..=parse_number(last)?)
+}
+
+fn parse_hp(s: &str) -> Result<u8, String> {
+    match parse_number(s)? {
+        hp @ 1..=MAX_HP => Ok(hp),
+        _ => Err(format!("hp must be between 1 and {MAX_HP}, not {s}")),
+    }
+}
+
+fn parse_letter(s: &str) -> Result<char, String> {
+    match s.chars().collect::<Vec<_>>()[..] {
+        [letter] if letter.is_ascii_lowercase() => Ok(letter),
+        _ => Err(format!("letter must be a single lowercase letter, not '{s}'")),
+    }
+}
+
+// Properties of one piece with the line numbers they were on
+struct Fields<'a> {
+    name: &'a str,
+    line_number: usize,
+    values: HashMap<&'a str, (usize, &'a str)>,
+}
+
+impl<'a> Fields<'a> {
+    fn optional<T>(&self, key: &str, parse: impl Fn(&'a str) -> Result<T, String>) -> Result<Option<T>, String> {
+        match self.values.get(key) {
+            Some(&(line_number, value)) => parse(value).map(Some).map_err(|e| format!("line {line_number}: {e}")),
+            None => Ok(None),
+        }
+    }
+
+    fn required<T>(&self, key: &str, parse: impl Fn(&'a str) -> Result<T, String>) -> Result<T, String> {
+        self.optional(key, parse)?.ok_or_else(|| format!("line {}: piece {} has no '{key}'", self.line_number, self.name))
+    }
+
+    // Names are the names of all pieces in order, for referring to other pieces
+    fn into_def(self, names: &[&str]) -> Result<PieceDef, String> {
+        let parse_piece = |s: &str| match names.iter().position(|name| *name == s) {
+            Some(index) if s != self.name => Ok(UnitType(index as u8)),
+            Some(_) => Err(format!("{s} can't promote into itself")),
+            None => Err(format!("unknown piece '{s}'")),
+        };
+        Ok(PieceDef {
+            name: self.name.to_owned(),
+            letter: self.required("letter", parse_letter)?,
+            sprite: self.required("sprite", |s| Ok(s.to_owned()))?,
+            moves: self.required("moves", parse_betza)?,
+            price: self.required("price", parse_number)?,
+            reward: self.required("reward", parse_number)?,
+            value: self.required("value", parse_number)?,
+            hp: self.optional("hp", parse_hp)?.unwrap_or(1),
+            shop: self.optional("shop", parse_bool)?.unwrap_or(false),
+            enemy_floors: self.optional("enemy_floors", parse_floors)?,
+            promotion: self.optional("promotion", parse_piece)?,
+            description: self.required("description", |s| Ok(s.to_owned()))?,
+        })
+    }
+}
+
+pub fn parse_pieces(text: &str) -> Result<Vec<PieceDef>, String> {
+    let mut all_fields = vec![];
+    let mut current: Option<Fields> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        let (key, value) = line.split_once(char::is_whitespace).map(|(k, v)| (k, v.trim())).unwrap_or((line, ""));
+        if key == "piece" {
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                return Err(format!("line {line_number}: expected a piece name without spaces"));
+            }
+            all_fields.extend(current.take());
+            current = Some(Fields { name: value, line_number, values: HashMap::new() });
+            continue
+        }
+        let Some(fields) = &mut current else {
+            return Err(format!("line {line_number}: expected 'piece' before '{key}'"));
+        };
+        if !["letter", "sprite", "moves", "price", "reward", "value", "hp", "shop", "enemy_floors", "promotion", "description"].contains(&key) {
+            return Err(format!("line {line_number}: unknown property '{key}'"));
+        }
+        if fields.values.insert(key, (line_number, value)).is_some() {
+            return Err(format!("line {line_number}: '{key}' is given twice for {}", fields.name));
+        }
+    }
+    all_fields.extend(current);
+    if all_fields.len() > u8::MAX as usize {
+        return Err(format!("at most {} pieces can be defined", u8::MAX));
+    }
+    let names = all_fields.iter().map(|f| f.name).collect::<Vec<_>>();
+    let pieces = all_fields.into_iter().map(|f| f.into_def(&names)).collect::<Result<Vec<_>, _>>()?;
+
+    let builtin = [
+        (UnitType::Pawn, "Pawn"), (UnitType::Knight, "Knight"), (UnitType::King, "King"), (UnitType::Bishop, "Bishop"),
+        (UnitType::Jester, "Jester"), (UnitType::Rook, "Rook"), (UnitType::Queen, "Queen"), (UnitType::Archbishop, "Archbishop"),
+    ];
+    for (unit_type, name) in builtin {
+        if pieces.get(unit_type.index()).map(|p: &PieceDef| p.name.as_str()) != Some(name) {
+            return Err(format!("piece number {} must be {name}", unit_type.index() + 1));
+        }
+    }
+    for (i, piece) in pieces.iter().enumerate() {
+        if let Some(other) = pieces[..i].iter().find(|p| p.name == piece.name || p.letter == piece.letter) {
+            return Err(format!("{} and {} have the same name or letter", other.name, piece.name));
+        }
+    }
+    Ok(pieces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn betza_test() {
+        let directions = |notation| parse_betza(notation).unwrap().iter().map(|m| (m.directions.len(), m.range)).collect::<Vec<_>>();
+        assert_eq!(directions("W"), [(4, 1)]);
+        assert_eq!(directions("K"), [(4, 1), (4, 1)]);
+        assert_eq!(directions("BN"), [(4, MAX_RANGE), (8, 1)]);
+        assert_eq!(directions("CZ"), [(8, 1), (8, 1)]);
+        assert_eq!(directions("NN"), [(8, MAX_RANGE)]);
+        assert_eq!(directions("W3F"), [(4, 3), (4, 1)]);
+        assert_eq!(parse_betza("N").unwrap()[0].directions[..4], [ivec2(-2, -1), ivec2(-1, -2), ivec2(1, -2), ivec2(2, -1)]);
+
+        let modes = |notation| parse_betza(notation).unwrap().iter().map(|m| (m.moves, m.captures, m.jump)).collect::<Vec<_>>();
+        assert_eq!(modes("mRcpR"), [(true, false, Jump::None), (false, true, Jump::Hop)]);
+        assert_eq!(modes("gQ"), [(true, true, Jump::Grasshopper), (true, true, Jump::Grasshopper)]);
+        assert_eq!(modes("mFlF"), [(true, false, Jump::None), (false, true, Jump::Locust)]);
+        assert_eq!(directions("cpR"), [(4, MAX_RANGE)]);
+
+        assert_eq!(parse_betza("").err().unwrap(), "moves are empty");
+        assert_eq!(parse_betza("WX").err().unwrap(), "unknown atom 'X' in 'WX'");
+        assert_eq!(parse_betza("KW").err().unwrap(), "'KW' has the same leap more than once");
+        assert_eq!(parse_betza("R0").err().unwrap(), "range of 'R' in 'R0' must be between 1 and 20");
+        assert_eq!(parse_betza("RmR").err().unwrap(), "'RmR' has the same leap more than once");
+        assert_eq!(parse_betza("mcR").err().unwrap(), "modifiers of 'mcR' contradict each other");
+        assert_eq!(parse_betza("mlF").err().unwrap(), "modifiers of 'mlF' contradict each other");
+        assert_eq!(parse_betza("xR").err().unwrap(), "unknown modifier 'x' in 'xR'");
+        assert_eq!(parse_betza("Rp").err().unwrap(), "'Rp' ends without an atom");
+    }
+
+    #[test]
+    fn pieces_file_test() {
+        assert_eq!(PIECES.len(), 11);
+        assert_eq!(UnitType::Queen.def().moves, parse_betza("BR").unwrap());
+
+        let pieces = include_str!("../pieces.txt");
+        let with = |extra: &str| parse_pieces(&format!("{pieces}\n{extra}")).err();
+        assert_eq!(with("piece Camel\nletter m\nsprite horse\nmoves C\nprice 3\nreward 3\nvalue 30\ndescription Leaps (3, 1)."), None);
+        assert_eq!(with("piece Camel\nletter m\nmoves C"), Some("line 139: piece Camel has no 'sprite'".to_owned()));
+        assert_eq!(with("piece Camel\nletter m\nletter x"), Some("line 141: 'letter' is given twice for Camel".to_owned()));
+        assert_eq!(with("piece Camel\nletter x\nsprite horse\nmoves C0"), Some("line 142: range of 'C' in 'C0' must be between 1 and 20".to_owned()));
+        assert_eq!(with("piece Camel\nletter n\nsprite horse\nmoves C\nprice 3\nreward 3\nvalue 30\ndescription Camel."), Some("Knight and Camel have the same name or letter".to_owned()));
+        assert_eq!(with("piece Camel\nspeed 3"), Some("line 140: unknown property 'speed'".to_owned()));
+        assert_eq!(with("piece Camel\nletter m\nsprite horse\nmoves C\nprice 3\nreward 3\nvalue 30\npromotion Camel"), Some("line 146: Camel can't promote into itself".to_owned()));
+        assert_eq!(with("piece Camel\nletter m\nsprite horse\nmoves C\nprice 3\nreward 3\nvalue 30\npromotion Dragon"), Some("line 146: unknown piece 'Dragon'".to_owned()));
+        assert_eq!(with("piece Camel\nletter m\nsprite horse\nmoves C\nprice 3\nreward 3\nvalue 30\nhp 9"), Some("line 146: hp must be between 1 and 8, not 9".to_owned()));
+        assert_eq!(UnitType::Pawn.def().promotion, Some(UnitType::Queen));
+        assert_eq!((UnitType::Pawn.def().hp, UnitType::Queen.def().hp), (1, 2));
+        assert_eq!(parse_pieces("letter p").err().unwrap(), "line 1: expected 'piece' before 'letter'");
+        assert_eq!(parse_pieces("").err().unwrap(), "piece number 1 must be Pawn");
+    }
+}
@@ -0,0 +1,220 @@
+// Text notation for board positions, similar to FEN in chess. Example:
+//
+//     ..r../.#.#./K.J(n).</..... p
+//
+// The first field lists the rows from top to bottom separated by '/'. Tiles use the characters of
+// TileMap::from ('#' wall, '.' floor, '<' stairs) except that empty tiles are written as '_'.
+// A unit letter stands on a floor tile, uppercase for the player and lowercase for the AI. The
+// letters are given in pieces.txt, such as P pawn, N knight, K king and J jester. A unit can be
+// followed by attributes in parentheses: the letter of the jester's current move style, the number
+// of captures of a unit that can promote, '-' for each hit taken, '+' for each point of armor and
+// '<' when the unit is standing on the stairs. The second field is the side to move, 'p' or 'a'.
+use std::{fmt, str::FromStr, sync::Arc};
+use enum_iterator::all;
+use glam::ivec2;
+
+use crate::{boardstate::BoardState, tile::*, unit::*};
+
+// Move style of jesters that have not captured anything yet
+const DEFAULT_JESTER_TYPE: UnitType = UnitType::Rook;
+
+pub struct Position {
+    pub state: BoardState,
+    pub side_to_move: Team,
+}
+
+impl Position {
+    pub fn new(state: BoardState, side_to_move: Team) -> Self {
+        Position { state, side_to_move }
+    }
+}
+
+fn unit_letter(unit_type: UnitType) -> char {
+    unit_type.def().letter
+}
+
+fn letter_unit(chr: char) -> Option<UnitType> {
+    UnitType::all().find(|t| unit_letter(*t) == chr.to_ascii_lowercase())
+}
+
+fn side_letter(team: Team) -> char {
+    match team {
+        Team::Player => 'p',
+        Team::Ai => 'a',
+    }
+}
+
+fn parse_tile(chr: char) -> Option<Tile> {
+    match chr {
+        '_' => Some(Tile::Empty),
+        '#' | '.' | '<' => Some(chr_to_tile(chr)),
+        _ => None,
+    }
+}
+
+fn parse_row(row: &str, y: i32, tiles: &mut Vec<Tile>, units: &mut Vec<Unit>) -> Result<(), String> {
+    let mut chars = row.chars().peekable();
+    let mut x = 0;
+    while let Some(chr) = chars.next() {
+        let at = || format!("row {}, column {}", y + 1, x + 1);
+        if let Some(tile) = parse_tile(chr) {
+            tiles.push(tile);
+        }
+        else if let Some(unit_type) = letter_unit(chr) {
+            let team = if chr.is_ascii_uppercase() { Team::Player } else { Team::Ai };
+            let mut unit = Unit { pos: ivec2(x, y), unit_type, jester_type: DEFAULT_JESTER_TYPE, team, captures: 0, damage: 0, armor: 0 };
+            let mut tile = Tile::Floor;
+            if chars.next_if_eq(&'(').is_some() {
+                loop {
+                    match chars.next() {
+                        Some(')') => break,
+                        Some('<') if tile == Tile::Floor => tile = Tile::Stairs,
+                        Some(attr) if unit.can_promote() && attr.is_ascii_digit() => {
+                            unit.captures = (attr as u8 - b'0').min(PROMOTION_CAPTURES);
+                        }
+                        Some('-') if unit.hp() > 1 => unit.damage += 1,
+                        Some('+') if unit.armor < MAX_ARMOR => unit.armor += 1,
+                        Some(attr) if unit_type == UnitType::Jester && attr.is_ascii_lowercase() => {
+                            unit.jester_type = letter_unit(attr)
+                                .filter(|t| *t != UnitType::Jester)
+                                .ok_or_else(|| format!("{}: '{attr}' is not a move style for a jester", at()))?;
+                        }
+                        Some(attr) => return Err(format!("{}: unexpected attribute '{attr}' for '{chr}'", at())),
+                        None => return Err(format!("{}: missing ')' after '{chr}'", at())),
+                    }
+                }
+            }
+            tiles.push(tile);
+            units.push(unit);
+        }
+        else {
+            return Err(format!("{}: unknown character '{chr}'", at()));
+        }
+        x += 1;
+    }
+    Ok(())
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        let [board, side] = fields[..] else {
+            return Err(format!("expected 2 fields separated by spaces, found {}", fields.len()));
+        };
+        let side_to_move = all::<Team>()
+            .find(|t| side_letter(*t).to_string() == side)
+            .ok_or_else(|| format!("unknown side to move '{side}', expected 'p' or 'a'"))?;
+
+        let mut tiles = vec![];
+        let mut units = vec![];
+        let mut width = 0;
+        let rows = board.split('/').collect::<Vec<_>>();
+        for (y, row) in rows.iter().enumerate() {
+            parse_row(row, y as i32, &mut tiles, &mut units)?;
+            if y == 0 {
+                width = tiles.len();
+                if width == 0 {
+                    return Err("row 1 is empty".to_owned());
+                }
+            }
+            else if tiles.len() != width * (y + 1) {
+                return Err(format!("row {} has {} tiles, expected {width}", y + 1, tiles.len() - width * y));
+            }
+        }
+
+        let mut tilemap = TileMap::new(width, rows.len());
+        for (i, tile) in tiles.into_iter().enumerate() {
+            tilemap.set(ivec2((i % width) as i32, (i / width) as i32), tile);
+        }
+        Ok(Position { state: BoardState::new(Arc::new(tilemap), units), side_to_move })
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tilemap = &self.state.tilemap;
+        for y in 0..tilemap.get_height() {
+            if y > 0 {
+                write!(f, "/")?;
+            }
+            for x in 0..tilemap.get_width() {
+                let pos = ivec2(x as i32, y as i32);
+                let tile = tilemap.get_unchecked(pos);
+                let Some(unit) = self.state.get_unit_at(&pos) else {
+                    write!(f, "{}", if tile == Tile::Empty { '_' } else { tile_to_chr(tile) })?;
+                    continue
+                };
+
+                let letter = unit_letter(unit.unit_type);
+                write!(f, "{}", if unit.team == Team::Player { letter.to_ascii_uppercase() } else { letter })?;
+                let mut attributes = String::new();
+                if unit.unit_type == UnitType::Jester && unit.jester_type != DEFAULT_JESTER_TYPE {
+                    attributes.push(unit_letter(unit.jester_type));
+                }
+                if unit.captures > 0 {
+                    attributes.push((b'0' + unit.captures) as char);
+                }
+                attributes.push_str(&"-".repeat(unit.damage as usize));
+                attributes.push_str(&"+".repeat(unit.armor as usize));
+                if tile == Tile::Stairs {
+                    attributes.push('<');
+                }
+                if !attributes.is_empty() {
+                    write!(f, "({attributes})")?;
+                }
+            }
+        }
+        write!(f, " {}", side_letter(self.side_to_move))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_round_trip_test() {
+        let text = "..r(+).q(-+)/_#.#_/K.J(n)j</Q(<)..P(2<)A a";
+        let position = text.parse::<Position>().unwrap();
+        assert_eq!(position.to_string(), text);
+        assert_eq!(position.side_to_move, Team::Ai);
+
+        let state = &position.state;
+        assert_eq!(state.tilemap.to_strings(), [".....", " #.# ", "....<", "<..<."]);
+        assert_eq!(state.stairs, Some(ivec2(4, 2)));
+        assert_eq!(state.units.len(), 8);
+        assert_eq!(state.get_unit_at(&ivec2(3, 3)).unwrap().captures, 2);
+        assert_eq!(state.get_unit_at(&ivec2(2, 0)), Some(&Unit { pos: ivec2(2, 0), unit_type: UnitType::Rook, jester_type: DEFAULT_JESTER_TYPE, team: Team::Ai, captures: 0, damage: 0, armor: 1 }));
+        assert_eq!(state.get_unit_at(&ivec2(2, 2)).unwrap().jester_type, UnitType::Knight);
+        let queen = state.get_unit_at(&ivec2(4, 0)).unwrap();
+        assert_eq!((queen.hp(), queen.armor), (1, 1));
+        assert_eq!(state.get_unit_at(&ivec2(3, 2)).unwrap().jester_type, DEFAULT_JESTER_TYPE);
+        assert_eq!(state.get_unit_at(&ivec2(0, 3)).unwrap().team, Team::Player);
+        assert_eq!(state.hash, state.compute_hash());
+
+        // Positions built by hand can be written out
+        let mut state = BoardState { tilemap: Arc::new(TileMap::from(["..<"].as_slice())), ..Default::default() };
+        state.units.push(Unit { pos: ivec2(0, 0), unit_type: UnitType::King, team: Team::Player, ..Default::default() });
+        state.rebuild_indices();
+        assert_eq!(Position::new(state, Team::Player).to_string(), "K.< p");
+    }
+
+    #[test]
+    fn position_error_test() {
+        let error = |text: &str| text.parse::<Position>().err().unwrap();
+        assert_eq!(error("..."), "expected 2 fields separated by spaces, found 1");
+        assert_eq!(error("... w"), "unknown side to move 'w', expected 'p' or 'a'");
+        assert_eq!(error("../.x. p"), "row 2, column 2: unknown character 'x'");
+        assert_eq!(error("../... p"), "row 2 has 3 tiles, expected 2");
+        assert_eq!(error("/.. p"), "row 1 is empty");
+        assert_eq!(error(".J(j) p"), "row 1, column 2: 'j' is not a move style for a jester");
+        assert_eq!(error("R(n). p"), "row 1, column 1: unexpected attribute 'n' for 'R'");
+        assert_eq!(error("R(1). p"), "row 1, column 1: unexpected attribute '1' for 'R'");
+        assert_eq!(error("R(-). p"), "row 1, column 1: unexpected attribute '-' for 'R'");
+        assert_eq!(error("Q(--). p"), "row 1, column 1: unexpected attribute '-' for 'Q'");
+        assert_eq!(error("K(++++). p"), "row 1, column 1: unexpected attribute '+' for 'K'");
+        assert_eq!(error(".k(< p"), "row 1, column 2: missing ')' after 'k'");
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt::{self, Display};
+use glam::{IVec2, ivec2};
+
+use crate::{boardstate::Move, difficulty::Difficulty, engine::EngineKind, savegame::{Reader, parse_bool, parse_enum, parse_number}, unit::UnitType};
+
+pub const REPLAY_PATH: &str = "kings_conquest.replay";
+
+const REPLAY_HEADER: &str = "kings_conquest_replay";
+const REPLAY_VERSION: u32 = 3;
+
+// Player input, or something decided outside of the seeded gameplay RNG
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReplayEvent {
+    PlayerMove(Move),
+    // AI search depends on timing, so its moves are recorded instead of searched again
+    AiMove(Option<Move>),
+    // Shop positions are tiles of the 5x5 shop board
+    Buy(UnitType),
+    Sell(IVec2),
+    BuyArmor(IVec2),
+    Place { from: IVec2, to: IVec2 },
+    NextFloor,
+    GiveUp,
+}
+
+// A run recorded as its seed and settings followed by everything that happened
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub engine: EngineKind,
+    pub predictable: bool,
+    pub classic_rules: bool,
+    pub hit_points: bool,
+    pub events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, difficulty: Difficulty, engine: EngineKind, predictable: bool, classic_rules: bool, hit_points: bool) -> Self {
+        Replay { seed, difficulty, engine, predictable, classic_rules, hit_points, events: vec![] }
+    }
+
+    pub fn from_text(text: &str) -> Result<Replay, String> {
+        let mut reader = Reader::new(text);
+
+        let version = reader.value(REPLAY_HEADER, parse_number::<u32>).map_err(|_| "not a replay file".to_owned())?;
+        if version == 0 || version > REPLAY_VERSION {
+            return Err(format!("unsupported replay version {version}"));
+        }
+
+        let mut replay = Replay::new(
+            reader.value("seed", parse_number)?,
+            reader.value("difficulty", parse_enum)?,
+            reader.value("engine", parse_enum)?,
+            reader.value("predictable", parse_bool)?,
+            // Version 1 had no classic rules
+            if version >= 2 { reader.value("classic_rules", parse_bool)? } else { false },
+            // Earlier versions had no hit points, so their captures are replayed without them
+            if version >= 3 { reader.value("hit_points", parse_bool)? } else { false },
+        );
+
+        let event_count: usize = reader.value("events", parse_number)?;
+        for _ in 0..event_count {
+            let line = reader.next_line()?;
+            let event = parse_event(line).map_err(|e| format!("line {}: {e}", reader.line_number))?;
+            replay.events.push(event);
+        }
+        Ok(replay)
+    }
+}
+
+fn parse_ivec2(x: &str, y: &str) -> Result<IVec2, String> {
+    Ok(ivec2(parse_number(x)?, parse_number(y)?))
+}
+
+// Moves are written as from and to, followed by the square of a unit that was jumped over and
+// captured and the unit type that the unit promoted into
+fn parse_move(mut parts: &[&str]) -> Result<Move, String> {
+    let mut promotion = None;
+    if let [coords @ .., unit_type] = parts {
+        if unit_type.parse::<i32>().is_err() {
+            promotion = Some(unit_type.parse()?);
+            parts = coords;
+        }
+    }
+    let (from, to, capture) = match parts {
+        [fx, fy, tx, ty] => (parse_ivec2(fx, fy)?, parse_ivec2(tx, ty)?, None),
+        [fx, fy, tx, ty, cx, cy] => (parse_ivec2(fx, fy)?, parse_ivec2(tx, ty)?, Some(parse_ivec2(cx, cy)?)),
+        _ => return Err(format!("expected 4 or 6 coordinates for a move, found {}", parts.len())),
+    };
+    Ok(Move { from, to, capture, promotion })
+}
+
+fn write_move(f: &mut fmt::Formatter, m: &Move) -> fmt::Result {
+    write!(f, "{} {} {} {}", m.from.x, m.from.y, m.to.x, m.to.y)?;
+    if let Some(capture) = m.capture {
+        write!(f, " {} {}", capture.x, capture.y)?;
+    }
+    if let Some(promotion) = m.promotion {
+        write!(f, " {promotion:?}")?;
+    }
+    Ok(())
+}
+
+fn parse_event(line: &str) -> Result<ReplayEvent, String> {
+    let parts = line.split_whitespace().collect::<Vec<_>>();
+    let event = match parts.as_slice() {
+        ["move", coords @ ..] => ReplayEvent::PlayerMove(parse_move(coords)?),
+        ["ai"] => ReplayEvent::AiMove(None),
+        ["ai", coords @ ..] => ReplayEvent::AiMove(Some(parse_move(coords)?)),
+        ["buy", unit_type] => ReplayEvent::Buy(unit_type.parse()?),
+        ["sell", x, y] => ReplayEvent::Sell(parse_ivec2(x, y)?),
+        ["armor", x, y] => ReplayEvent::BuyArmor(parse_ivec2(x, y)?),
+        ["place", fx, fy, tx, ty] => ReplayEvent::Place { from: parse_ivec2(fx, fy)?, to: parse_ivec2(tx, ty)? },
+        ["next_floor"] => ReplayEvent::NextFloor,
+        ["give_up"] => ReplayEvent::GiveUp,
+        _ => return Err(format!("unknown event '{line}'")),
+    };
+    Ok(event)
+}
+
+impl Display for ReplayEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayEvent::PlayerMove(m) => {
+                write!(f, "move ")?;
+                write_move(f, m)
+            }
+            ReplayEvent::AiMove(Some(m)) => {
+                write!(f, "ai ")?;
+                write_move(f, m)
+            }
+            ReplayEvent::AiMove(None) => write!(f, "ai"),
+            ReplayEvent::Buy(unit_type) => write!(f, "buy {unit_type:?}"),
+            ReplayEvent::Sell(p) => write!(f, "sell {} {}", p.x, p.y),
+            ReplayEvent::BuyArmor(p) => write!(f, "armor {} {}", p.x, p.y),
+            ReplayEvent::Place { from, to } => write!(f, "place {} {} {} {}", from.x, from.y, to.x, to.y),
+            ReplayEvent::NextFloor => write!(f, "next_floor"),
+            ReplayEvent::GiveUp => write!(f, "give_up"),
+        }
+    }
+}
+
+impl Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{REPLAY_HEADER} {REPLAY_VERSION}")?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "difficulty {:?}", self.difficulty)?;
+        writeln!(f, "engine {:?}", self.engine)?;
+        writeln!(f, "predictable {}", if self.predictable { 1 } else { 0 })?;
+        writeln!(f, "classic_rules {}", if self.classic_rules { 1 } else { 0 })?;
+        writeln!(f, "hit_points {}", if self.hit_points { 1 } else { 0 })?;
+        writeln!(f, "events {}", self.events.len())?;
+        for event in &self.events {
+            writeln!(f, "{event}")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn write_replay(replay: &Replay) -> Result<(), String> {
+    std::fs::write(REPLAY_PATH, replay.to_string()).map_err(|e| e.to_string())
+}
+
+pub fn read_replay() -> Result<Replay, String> {
+    let text = std::fs::read_to_string(REPLAY_PATH).map_err(|e| e.to_string())?;
+    Replay::from_text(&text)
+}
+
+pub fn has_replay() -> bool {
+    std::path::Path::new(REPLAY_PATH).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_round_trip_test() {
+        let mut replay = Replay::new(42, Difficulty::Hard, EngineKind::Minimax, false, true, true);
+        replay.events = vec![
+            ReplayEvent::PlayerMove(Move { from: ivec2(1, 2), to: ivec2(3, 4), ..Default::default() }),
+            ReplayEvent::AiMove(Some(Move { from: ivec2(5, 6), to: ivec2(7, 8), ..Default::default() })),
+            ReplayEvent::PlayerMove(Move { from: ivec2(1, 1), to: ivec2(3, 3), capture: Some(ivec2(2, 2)), promotion: None }),
+            ReplayEvent::AiMove(Some(Move { from: ivec2(0, 1), to: ivec2(0, 2), capture: None, promotion: Some(UnitType::Queen) })),
+            ReplayEvent::AiMove(None),
+            ReplayEvent::Buy(UnitType::Archbishop),
+            ReplayEvent::Sell(ivec2(0, 4)),
+            ReplayEvent::BuyArmor(ivec2(2, 2)),
+            ReplayEvent::Place { from: ivec2(1, 1), to: ivec2(3, 2) },
+            ReplayEvent::NextFloor,
+            ReplayEvent::GiveUp,
+        ];
+
+        let text = replay.to_string();
+        assert_eq!(Replay::from_text(&text).unwrap(), replay);
+
+        let broken = text.replace("next_floor", "jump");
+        assert!(Replay::from_text(&broken).err().unwrap().starts_with("line 18: unknown event"));
+
+        let broken = text.replace("move 1 1 3 3 2 2", "move 1 1 3 3 2");
+        assert_eq!(Replay::from_text(&broken).err().unwrap(), "line 11: expected 4 or 6 coordinates for a move, found 5");
+
+        let broken = text.replace("ai 0 1 0 2 Queen", "ai 0 1 0 2 Dragon");
+        assert_eq!(Replay::from_text(&broken).err().unwrap(), "line 12: unknown value 'Dragon'");
+
+        // Replays of earlier versions are played with the old rules
+        let version_2 = text.replace("kings_conquest_replay 3", "kings_conquest_replay 2").replace("hit_points 1\n", "");
+        let loaded = Replay::from_text(&version_2).unwrap();
+        assert!(loaded.classic_rules && !loaded.hit_points);
+        let version_1 = version_2.replace("kings_conquest_replay 2", "kings_conquest_replay 1").replace("classic_rules 1\n", "");
+        let loaded = Replay::from_text(&version_1).unwrap();
+        assert!(!loaded.classic_rules && !loaded.hit_points);
+        assert_eq!(loaded.events, replay.events);
+    }
+}
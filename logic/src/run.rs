@@ -0,0 +1,547 @@
+use std::sync::Arc;
+use glam::{IVec2, ivec2};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{boardstate::{BoardState, GameEnd, Move, Promotions, splitmix64}, difficulty::Difficulty, engine::EngineKind, evaluation::SearchSettings, mapgenerator::*, replay::*, savegame::*, tile::*, unit::*, utils};
+
+pub const LAST_FLOOR: usize = 12;
+
+pub const HINTS_PER_RUN: u32 = 3;
+
+// King waiting on the last floor takes this many hits more than the player's
+const BOSS_ARMOR: u8 = 2;
+
+// Units sold in the shop, cheapest first
+pub fn shop_units() -> Vec<UnitType> {
+    let mut units = UnitType::all().filter(|t| t.def().shop).collect::<Vec<_>>();
+    units.sort_by_key(|t| unit_buy_price(*t));
+    units
+}
+
+// Material the enemies of a floor are bought with
+pub fn enemy_budget(floor: usize) -> i32 {
+    1 + 2 * floor as i32
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InitialPosition {
+    pub offset: IVec2, // Offset in relation to player's king
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Won,
+    Lost,
+}
+
+// Floors use their own RNG streams derived from the run seed, so a run can be replayed from the seed
+const MAP_STREAM: u64 = 0;
+const ENEMY_STREAM: u64 = 1;
+
+fn floor_rng(seed: u64, floor: usize, stream: u64) -> SmallRng {
+    SmallRng::seed_from_u64(splitmix64(seed ^ splitmix64(floor as u64 * 2 + stream)))
+}
+
+// State of a run from the first floor to the end. Nothing here knows about rendering or files,
+// saving and replays are written only when asked.
+pub struct Run {
+    pub seed: u64,
+    // AI randomness, floors don't depend on it
+    rng: SmallRng,
+    pub material: i32,
+    pub player_turn: bool,
+    pub tilemap: TileMap,
+    // Room the player started the floor in, where AI units promote
+    pub start_room: Option<(IVec2, IVec2)>,
+    // Player's units know where they start on the next floor
+    pub units: Vec<(Unit, Option<InitialPosition>)>,
+    pub is_shopping: bool,
+    pub floor: usize,
+    last_gen_result: Option<MapGeneratorResult>,
+    pub difficulty: Difficulty,
+    pub engine: EngineKind,
+    pub predictable: bool,
+    // Kings can't be left capturable and checkmate decides the run
+    pub classic_rules: bool,
+    // Units have armor and pieces with more hp take several hits to capture
+    pub hit_points: bool,
+    pub hints_left: u32,
+    pub outcome: Option<Outcome>,
+    // Inputs of the run so far, None when the run is a replay being watched
+    pub replay: Option<Replay>,
+}
+
+impl Run {
+    fn empty(difficulty: Difficulty, engine: EngineKind, predictable: bool, classic_rules: bool, hit_points: bool, seed: u64) -> Self {
+        Run {
+            seed,
+            rng: SmallRng::seed_from_u64(seed),
+            material: 20,
+            player_turn: true,
+            tilemap: TileMap::new(1, 1),
+            start_room: None,
+            units: vec![],
+            is_shopping: false,
+            floor: 0,
+            last_gen_result: None,
+            difficulty,
+            engine,
+            predictable,
+            classic_rules,
+            hit_points,
+            hints_left: HINTS_PER_RUN,
+            outcome: None,
+            replay: Some(Replay::new(seed, difficulty, engine, predictable, classic_rules, hit_points)),
+        }
+    }
+
+    // Starts a run on the first floor
+    pub fn new(difficulty: Difficulty, engine: EngineKind, predictable: bool, classic_rules: bool, hit_points: bool, seed: u64) -> Self {
+        let mut run = Run::empty(difficulty, engine, predictable, classic_rules, hit_points, seed);
+        let units = [(UnitType::King, ivec2(0, 0)), (UnitType::Knight, ivec2(1, 0)), (UnitType::Bishop, ivec2(-1, 0))];
+        run.pre_generate_next_floor();
+        run.generate_next_floor(units.map(|(u, p)| (Unit { unit_type: u, ..Default::default() }, InitialPosition{ offset: p })).as_slice());
+        run
+    }
+
+    pub fn make_save_data(&mut self) -> SaveData {
+        // RNG state can't be stored, so continue from a fresh seed that is saved instead
+        let rng_seed = self.rng.gen();
+        self.rng = SmallRng::seed_from_u64(rng_seed);
+
+        SaveData {
+            seed: self.seed,
+            floor: self.floor,
+            material: self.material,
+            rng_seed,
+            player_turn: self.player_turn,
+            is_shopping: self.is_shopping,
+            difficulty: self.difficulty,
+            engine: self.engine,
+            predictable: self.predictable,
+            classic_rules: self.classic_rules,
+            hit_points: self.hit_points,
+            hints_left: self.hints_left,
+            tilemap: self.tilemap.clone(),
+            start_room: self.start_room,
+            units: self.units.clone(),
+        }
+    }
+
+    // Replay of the run so far is continued if there is one
+    pub fn from_save_data(data: SaveData, replay: Option<Replay>) -> Self {
+        let mut run = Run::empty(data.difficulty, data.engine, data.predictable, data.classic_rules, data.hit_points, data.seed);
+        run.rng = SmallRng::seed_from_u64(data.rng_seed);
+        run.floor = data.floor;
+        run.material = data.material;
+        run.player_turn = data.player_turn;
+        run.is_shopping = data.is_shopping;
+        run.hints_left = data.hints_left;
+        run.tilemap = data.tilemap;
+        run.start_room = data.start_room;
+        run.units = data.units;
+        if let Some(replay) = replay {
+            run.replay = Some(replay);
+        }
+        run
+    }
+
+    // Writes the save and the replay so far
+    pub fn save(&mut self) -> Result<(), String> {
+        // Finished runs can't be continued
+        if self.outcome.is_some() {
+            return Ok(())
+        }
+        let data = self.make_save_data();
+        write_save(&data)?;
+        self.write_replay()
+    }
+
+    // Finished runs can't be continued, but they can be watched
+    pub fn end_run(&self) -> Result<(), String> {
+        // Replays being watched don't touch the files of the current run
+        if self.replay.is_some() {
+            delete_save();
+        }
+        self.write_replay()
+    }
+
+    fn write_replay(&self) -> Result<(), String> {
+        match &self.replay {
+            Some(replay) => write_replay(replay),
+            None => Ok(()),
+        }
+    }
+
+    fn record(&mut self, event: ReplayEvent) {
+        if let Some(replay) = &mut self.replay {
+            replay.events.push(event);
+        }
+    }
+
+    pub fn board_state(&self) -> BoardState {
+        let mut state = BoardState::new(Arc::new(self.tilemap.clone()), self.units.iter().map(|(u, _)| *u).collect());
+        state.promotions = Arc::new(Promotions { player_types: shop_units(), material: self.material, ai_zone: self.start_room });
+        state.classic_rules = self.classic_rules;
+        state.hit_points = self.hit_points;
+        state
+    }
+
+    pub fn unit_at(&self, pos: IVec2) -> Option<Unit> {
+        self.units.iter().map(|(u, _)| *u).find(|u| u.pos == pos)
+    }
+
+    pub fn is_on_stairs(&self) -> bool {
+        !self.is_shopping && self.board_state().is_on_stairs()
+    }
+
+    pub fn ai_search_settings(&mut self) -> SearchSettings {
+        self.difficulty.search_settings(self.rng.gen())
+    }
+
+    // Settings for searching the move the AI telegraphs. Seeded from the run and the position like
+    // the hint, the intent is searched every turn even when it isn't used.
+    pub fn intent_search_settings(&self) -> SearchSettings {
+        self.difficulty.search_settings(splitmix64(self.seed ^ self.board_state().hash))
+    }
+
+    // Uses up one hint, returns the settings for searching the hint. Seeded from the run and the
+    // position so that asking for a hint doesn't advance the game's rng.
+    pub fn use_hint(&mut self) -> Option<SearchSettings> {
+        if self.hints_left == 0 {
+            return None
+        }
+        self.hints_left -= 1;
+        Some(Difficulty::Hard.search_settings(splitmix64(self.seed ^ self.board_state().hash)))
+    }
+
+    // Picks the move the AI makes from the best move of its search
+    pub fn choose_ai_move(&mut self, best_move: Option<Move>) -> Option<Move> {
+        if self.rng.gen::<f32>() < self.difficulty.suboptimal_move_chance() {
+            let moves = self.board_state().get_valid_moves(Team::Ai);
+            if !moves.is_empty() {
+                return Some(moves[self.rng.gen_range(0..moves.len())])
+            }
+        }
+        best_move
+    }
+
+    // Returns the captured unit
+    pub fn make_player_move(&mut self, m: &Move) -> Option<Unit> {
+        assert!(self.player_turn);
+        self.record(ReplayEvent::PlayerMove(*m));
+        let captured = self.make_move(m);
+        self.player_turn = false;
+        self.update_game_end(Team::Ai);
+        captured
+    }
+
+    // Returns the captured unit
+    pub fn apply_ai_move(&mut self, m: Option<Move>) -> Option<Unit> {
+        assert!(!self.player_turn);
+        self.record(ReplayEvent::AiMove(m));
+        let captured = m.and_then(|m| {
+            assert_eq!(self.unit_at(m.from).map(|u| u.team), Some(Team::Ai));
+            self.make_move(&m)
+        });
+        self.player_turn = true;
+        self.update_game_end(Team::Player);
+        captured
+    }
+
+    // Under classic rules a checkmated king is lost like a captured one
+    fn update_game_end(&mut self, team_to_move: Team) {
+        match self.board_state().game_end(team_to_move) {
+            Some(GameEnd::Checkmate) => self.outcome = Some(if team_to_move == Team::Player { Outcome::Lost } else { Outcome::Won }),
+            // Stalemated player passes, the AI passes by itself when it has no moves
+            Some(GameEnd::Stalemate) if team_to_move == Team::Player => self.player_turn = false,
+            _ => {}
+        }
+    }
+
+    fn make_move(&mut self, m: &Move) -> Option<Unit> {
+        // A unit that survives the capture takes a hit and the attacker bounces back
+        let hit_points = self.hit_points;
+        if let Some((target, _)) = self.units.iter_mut().find(|(u, _)| u.pos == m.capture_pos() && hit_points && u.survives_hit()) {
+            target.take_hit();
+            return None
+        }
+
+        let captured_unit = self.units.iter().position(|(u, _)| u.pos == m.capture_pos()).map(|i| self.units.remove(i).0);
+
+        let (unit, _) = self.units.iter_mut().find(|(u, _)| u.pos == m.from).unwrap();
+        unit.pos = m.to;
+        if let Some(captured_unit) = captured_unit {
+            if unit.can_promote() {
+                unit.captures = (unit.captures + 1).min(PROMOTION_CAPTURES);
+            }
+            if unit.unit_type == UnitType::Jester {
+                unit.convert_jester(captured_unit);
+            }
+            if captured_unit.team == Team::Ai {
+                self.material += material_reward(captured_unit.unit_type);
+            }
+
+            if captured_unit.unit_type == UnitType::King {
+                self.outcome = Some(if captured_unit.team == Team::Player { Outcome::Lost } else { Outcome::Won });
+            }
+        }
+        if let Some(promotion) = m.promotion {
+            if unit.team == Team::Player {
+                self.material -= promotion_cost(unit.unit_type, promotion);
+            }
+            unit.unit_type = promotion;
+            unit.captures = 0;
+        }
+        captured_unit
+    }
+
+    // Returns the player's king that was removed
+    pub fn give_up(&mut self) -> Option<Unit> {
+        self.record(ReplayEvent::GiveUp);
+        let index = self.units.iter().position(|(u, _)| u.unit_type == UnitType::King && u.team == Team::Player)?;
+        self.outcome = Some(Outcome::Lost);
+        Some(self.units.remove(index).0)
+    }
+
+    fn add_unit(&mut self, pos: IVec2, unit_type: UnitType, armor: u8, team: Team, offset: Option<InitialPosition>) {
+        self.units.push((Unit { pos, unit_type, jester_type: UnitType::Rook, team, captures: 0, damage: 0, armor }, offset));
+    }
+
+    fn get_random_empty_tile(&self, rng: &mut SmallRng) -> Option<IVec2> {
+        for _ in 0..200 {
+            let x = rng.gen_range(0..self.tilemap.get_width());
+            let y = rng.gen_range(0..self.tilemap.get_width());
+            let pos = ivec2(x as i32, y as i32);
+
+            if self.tilemap.get(pos) == Some(Tile::Floor) && self.unit_at(pos).is_none() {
+                return Some(pos)
+            }
+        }
+        None
+    }
+
+    fn get_random_empty_away_from_spawn(&self, rng: &mut SmallRng, spawn_pos: IVec2) -> Option<IVec2> {
+        for _ in 0..100 {
+            if let Some(pos) = self.get_random_empty_tile(rng) {
+                if utils::dist2(&pos, &spawn_pos) > 4.0f32.powi(2) {
+                    return Some(pos)
+                }
+            }
+        }
+        None
+    }
+
+    // Units keep their armor from floor to floor, damage is healed
+    fn collect_player_units(&self) -> Vec<(Unit, InitialPosition)> {
+        self.units.iter().filter_map(|(u, p)| p.map(|p| (*u, p))).collect()
+    }
+
+    fn find_pos_for_new_unit(&self) -> Option<(IVec2, InitialPosition)> {
+        for y in 0..5 {
+            for x in 0..5 {
+                if self.unit_at(ivec2(x, y)).is_none() {
+                    return Some((ivec2(x, y), InitialPosition { offset: ivec2(x - 2, y - 2) }));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn buy_unit(&mut self, unit_type: UnitType) -> bool {
+        let price = unit_buy_price(unit_type);
+        match self.find_pos_for_new_unit() {
+            Some((pos, ipos)) if self.material >= price => {
+                self.record(ReplayEvent::Buy(unit_type));
+                self.add_unit(pos, unit_type, 0, Team::Player, Some(ipos));
+                self.material -= price;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn sell_unit(&mut self, pos: IVec2) -> bool {
+        match self.units.iter().position(|(u, _)| u.pos == pos && u.unit_type != UnitType::King) {
+            Some(index) => {
+                self.record(ReplayEvent::Sell(pos));
+                let (unit, _) = self.units.remove(index);
+                self.material += unit_buy_price(unit.unit_type) + unit.armor as i32 * ARMOR_PRICE;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Adds a point of armor to the unit on the shop board
+    pub fn buy_armor(&mut self, pos: IVec2) -> bool {
+        match self.units.iter().position(|(u, _)| u.pos == pos && u.armor < MAX_ARMOR) {
+            Some(index) if self.hit_points && self.material >= ARMOR_PRICE => {
+                self.record(ReplayEvent::BuyArmor(pos));
+                self.units[index].0.armor += 1;
+                self.material -= ARMOR_PRICE;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Moves a unit on the shop board, swapping places with the unit at the target tile
+    pub fn place_unit(&mut self, from: IVec2, to: IVec2) -> bool {
+        if self.unit_at(from).is_none() {
+            return false
+        }
+        if from != to {
+            self.record(ReplayEvent::Place { from, to });
+        }
+
+        for (unit, ipos) in &mut self.units {
+            let pos = if unit.pos == from { to } else if unit.pos == to { from } else { continue };
+            unit.pos = pos;
+            *ipos = Some(InitialPosition { offset: pos - ivec2(2, 2) });
+        }
+        true
+    }
+
+    // Player's units are placed on the 5x5 shop board around the king
+    pub fn show_shop(&mut self) {
+        let units = self.collect_player_units();
+        self.units.clear();
+        self.is_shopping = true;
+
+        for (unit, initial) in units {
+            self.add_unit(ivec2(2, 2) + initial.offset, unit.unit_type, unit.armor, Team::Player, Some(initial));
+        }
+    }
+
+    // Generates the map of the next floor, returns where the player starts on it
+    pub fn pre_generate_next_floor(&mut self) -> IVec2 {
+        let mut gen = MapGenerator::new(floor_rng(self.seed, self.floor + 1, MAP_STREAM), 15, 15);
+        let result = gen.generate();
+        let start_pos = result.start_pos;
+        self.last_gen_result = Some(result);
+        start_pos
+    }
+
+    // Leaves the shop for the floor made by pre_generate_next_floor
+    pub fn enter_next_floor(&mut self) {
+        self.record(ReplayEvent::NextFloor);
+        let units = self.collect_player_units();
+        self.generate_next_floor(&units);
+    }
+
+    fn generate_next_floor(&mut self, player_units: &[(Unit, InitialPosition)]) {
+        self.floor += 1;
+
+        self.tilemap = self.last_gen_result.as_ref().unwrap().tilemap.clone();
+        let start_pos = self.last_gen_result.as_ref().unwrap().start_pos;
+        self.start_room = Some(self.last_gen_result.as_ref().unwrap().start_room);
+
+        self.player_turn = true;
+        self.units.clear();
+        self.is_shopping = false;
+
+        for &(unit, initial) in player_units {
+            self.add_unit(start_pos + initial.offset, unit.unit_type, unit.armor, Team::Player, Some(initial));
+        }
+
+        let mut rng = floor_rng(self.seed, self.floor, ENEMY_STREAM);
+        let mut enemy_units = vec![];
+        let mut enemy_material = enemy_budget(self.floor);
+        let floor = self.floor;
+        let unit_list = UnitType::all()
+            .filter(|t| matches!(&t.def().enemy_floors, Some(floors) if floors.contains(&floor)))
+            .collect::<Vec<_>>();
+
+        while enemy_material > 0 {
+            let available = unit_list.iter().filter(|u| enemy_material >= unit_buy_price(**u)).collect::<Vec<_>>();
+            let index = rng.gen_range(0..available.len());
+            let unit = available[index];
+            enemy_material -= unit_buy_price(*unit);
+            enemy_units.push(*unit);
+        }
+
+        if self.floor == LAST_FLOOR {
+            enemy_units.push(UnitType::King);
+        }
+
+        while let Some(unit_type) = enemy_units.pop() {
+            if unit_type == UnitType::King {
+                if let Some(p) = self.tilemap.find_tile(Tile::Stairs) {
+                    let armor = if self.hit_points { BOSS_ARMOR } else { 0 };
+                    self.add_unit(p, unit_type, armor, Team::Ai, None);
+                    self.tilemap.set(p, Tile::Floor);
+                }
+            }
+            else if let Some(pos) = self.get_random_empty_away_from_spawn(&mut rng, start_pos) {
+                self.add_unit(pos, unit_type, 0, Team::Ai, None);
+            }
+        }
+    }
+
+    // Replays an event of a recorded run, fails if the event isn't possible
+    pub fn apply_replay_event(&mut self, event: ReplayEvent) -> Result<(), String> {
+        // Shop opens by itself once the move to the stairs has been made
+        if self.is_on_stairs() {
+            self.show_shop();
+        }
+
+        let valid_move = |run: &Run, m: &Move, team| !run.is_shopping && run.board_state().get_valid_moves(team).contains(m);
+        let ok = match event {
+            ReplayEvent::PlayerMove(m) if self.player_turn && valid_move(self, &m, Team::Player) => {
+                self.make_player_move(&m);
+                true
+            }
+            ReplayEvent::AiMove(m) if !self.player_turn && m.iter().all(|m| valid_move(self, m, Team::Ai)) => {
+                self.apply_ai_move(m);
+                true
+            }
+            ReplayEvent::Buy(unit_type) if self.is_shopping => self.buy_unit(unit_type),
+            ReplayEvent::Sell(pos) if self.is_shopping => self.sell_unit(pos),
+            ReplayEvent::BuyArmor(pos) if self.is_shopping => self.buy_armor(pos),
+            ReplayEvent::Place { from, to } if self.is_shopping => self.place_unit(from, to),
+            ReplayEvent::NextFloor if self.is_shopping => {
+                self.pre_generate_next_floor();
+                self.enter_next_floor();
+                true
+            }
+            ReplayEvent::GiveUp if !self.is_shopping => self.give_up().is_some(),
+            _ => false,
+        };
+        if ok { Ok(()) } else { Err(format!("can't replay '{event}'")) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_replay_test() {
+        let mut run = Run::new(Difficulty::Easy, EngineKind::Minimax, false, false, true, 1234);
+        assert_eq!(run.floor, 1);
+        assert!(run.units.iter().any(|(u, _)| u.team == Team::Ai));
+
+        // Same seed gives the same floor
+        let other = Run::new(Difficulty::Hard, EngineKind::Mcts, true, true, false, 1234);
+        assert_eq!(other.tilemap.to_strings(), run.tilemap.to_strings());
+        assert_eq!(other.units, run.units);
+
+        // Play a few turns with the first move available
+        for _ in 0..3 {
+            let m = run.board_state().get_valid_moves(Team::Player)[0];
+            run.make_player_move(&m);
+            let m = run.board_state().get_valid_moves(Team::Ai).first().copied();
+            run.apply_ai_move(m);
+        }
+
+        let replay = run.replay.clone().unwrap();
+        let mut replayed = Run::new(replay.difficulty, replay.engine, replay.predictable, replay.classic_rules, replay.hit_points, replay.seed);
+        replayed.replay = None;
+        for event in replay.events {
+            replayed.apply_replay_event(event).unwrap();
+        }
+        assert_eq!(replayed.units, run.units);
+        assert_eq!(replayed.material, run.material);
+    }
+}
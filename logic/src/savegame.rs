@@ -0,0 +1,349 @@
+use std::fmt::{self, Debug, Display};
+use enum_iterator::{Sequence, all};
+use glam::{IVec2, ivec2};
+
+use crate::{difficulty::Difficulty, engine::EngineKind, tile::TileMap, unit::*, run::InitialPosition};
+
+pub const SAVE_PATH: &str = "kings_conquest.sav";
+
+const SAVE_HEADER: &str = "kings_conquest_save";
+const SAVE_VERSION: u32 = 5;
+
+// Everything needed to resume a run
+pub struct SaveData {
+    // Run seed that floors are derived from
+    pub seed: u64,
+    pub floor: usize,
+    pub material: i32,
+    // Gameplay RNG is reseeded with this value when the game is saved
+    pub rng_seed: u64,
+    pub player_turn: bool,
+    pub is_shopping: bool,
+    pub difficulty: Difficulty,
+    pub engine: EngineKind,
+    pub predictable: bool,
+    pub classic_rules: bool,
+    pub hit_points: bool,
+    pub hints_left: u32,
+    pub tilemap: TileMap,
+    pub start_room: Option<(IVec2, IVec2)>,
+    pub units: Vec<(Unit, Option<InitialPosition>)>,
+}
+
+pub fn parse_enum<T: Sequence + Debug>(s: &str) -> Result<T, String> {
+    all::<T>().find(|t| format!("{t:?}") == s).ok_or_else(|| format!("unknown value '{s}'"))
+}
+
+pub fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid number '{s}'"))
+}
+
+pub fn parse_bool(s: &str) -> Result<bool, String> {
+    match s {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(format!("invalid boolean '{s}'")),
+    }
+}
+
+pub struct Reader<'a> {
+    lines: std::str::Lines<'a>,
+    pub line_number: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Reader { lines: text.lines(), line_number: 0 }
+    }
+
+    pub fn next_line(&mut self) -> Result<&'a str, String> {
+        self.line_number += 1;
+        self.lines.next().ok_or_else(|| format!("line {}: unexpected end of file", self.line_number))
+    }
+
+    // Reads a line of the form "key value..." and returns the values
+    pub fn field(&mut self, key: &str) -> Result<Vec<&'a str>, String> {
+        let line = self.next_line()?;
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some(k) if k == key => Ok(parts.collect()),
+            _ => Err(format!("line {}: expected '{key}'", self.line_number)),
+        }
+    }
+
+    pub fn value<T>(&mut self, key: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<T, String> {
+        let values = self.field(key)?;
+        let line_number = self.line_number;
+        match values.as_slice() {
+            [value] => parse(value).map_err(|e| format!("line {line_number}: {e}")),
+            _ => Err(format!("line {line_number}: expected one value for '{key}'")),
+        }
+    }
+}
+
+impl Display for SaveData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = |value: bool| if value { 1 } else { 0 };
+        writeln!(f, "{SAVE_HEADER} {SAVE_VERSION}")?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "floor {}", self.floor)?;
+        writeln!(f, "material {}", self.material)?;
+        writeln!(f, "rng {}", self.rng_seed)?;
+        writeln!(f, "player_turn {}", b(self.player_turn))?;
+        writeln!(f, "shopping {}", b(self.is_shopping))?;
+        writeln!(f, "difficulty {:?}", self.difficulty)?;
+        writeln!(f, "engine {:?}", self.engine)?;
+        writeln!(f, "predictable {}", b(self.predictable))?;
+        writeln!(f, "classic_rules {}", b(self.classic_rules))?;
+        writeln!(f, "hit_points {}", b(self.hit_points))?;
+        writeln!(f, "hints {}", self.hints_left)?;
+        writeln!(f, "map {} {}", self.tilemap.get_width(), self.tilemap.get_height())?;
+        // Rows are fenced so that empty tiles at the ends survive
+        for row in self.tilemap.to_strings() {
+            writeln!(f, "|{row}|")?;
+        }
+        match self.start_room {
+            Some((min, max)) => writeln!(f, "start_room {} {} {} {}", min.x, min.y, max.x, max.y)?,
+            None => writeln!(f, "start_room")?,
+        }
+        writeln!(f, "units {}", self.units.len())?;
+        for (unit, initial) in &self.units {
+            write!(f, "{:?} {:?} {:?} {} {} {} {} {}", unit.unit_type, unit.jester_type, unit.team, unit.pos.x, unit.pos.y, unit.captures, unit.damage, unit.armor)?;
+            match initial {
+                Some(initial) => writeln!(f, " {} {}", initial.offset.x, initial.offset.y)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SaveData {
+    pub fn from_text(text: &str) -> Result<SaveData, String> {
+        let mut reader = Reader::new(text);
+
+        let version = reader.value(SAVE_HEADER, parse_number::<u32>).map_err(|_| "not a save file".to_owned())?;
+        if version == 0 || version > SAVE_VERSION {
+            return Err(format!("unsupported save version {version}"));
+        }
+
+        // Version 1 had no run seed
+        let seed = if version >= 2 { Some(reader.value("seed", parse_number)?) } else { None };
+        let floor = reader.value("floor", parse_number)?;
+        let material = reader.value("material", parse_number)?;
+        let rng_seed = reader.value("rng", parse_number)?;
+        let player_turn = reader.value("player_turn", parse_bool)?;
+        let is_shopping = reader.value("shopping", parse_bool)?;
+        let difficulty = reader.value("difficulty", parse_enum)?;
+        let engine = reader.value("engine", parse_enum)?;
+        let predictable = reader.value("predictable", parse_bool)?;
+        // Version 3 had no classic rules
+        let classic_rules = if version >= 4 { reader.value("classic_rules", parse_bool)? } else { false };
+        // Version 4 had no hit points, its runs continue without them
+        let hit_points = if version >= 5 { reader.value("hit_points", parse_bool)? } else { false };
+        let hints_left = reader.value("hints", parse_number)?;
+
+        let size = reader.field("map")?;
+        let (width, height): (usize, usize) = match size.as_slice() {
+            [w, h] => (parse_number(w)?, parse_number(h)?),
+            _ => return Err(format!("line {}: expected map width and height", reader.line_number)),
+        };
+        let mut rows = vec![];
+        for _ in 0..height {
+            let line = reader.next_line()?;
+            let row = line.strip_prefix('|').and_then(|l| l.strip_suffix('|'));
+            match row {
+                Some(row) if row.chars().count() == width => rows.push(row),
+                _ => return Err(format!("line {}: expected a map row of width {width}", reader.line_number)),
+            }
+        }
+        if rows.is_empty() {
+            return Err("map is empty".to_owned());
+        }
+        let tilemap = TileMap::from(rows.as_slice());
+
+        // Version 2 had no start room or unit captures
+        let start_room = if version >= 3 {
+            match reader.field("start_room")?.as_slice() {
+                [x0, y0, x1, y1] => Some((ivec2(parse_number(x0)?, parse_number(y0)?), ivec2(parse_number(x1)?, parse_number(y1)?))),
+                [] => None,
+                _ => return Err(format!("line {}: expected the corners of the start room", reader.line_number)),
+            }
+        }
+        else {
+            None
+        };
+
+        let unit_count: usize = reader.value("units", parse_number)?;
+        let mut units = vec![];
+        for _ in 0..unit_count {
+            let line = reader.next_line()?;
+            let line_number = reader.line_number;
+            let parse_unit = || -> Result<(Unit, Option<InitialPosition>), String> {
+                let mut parts = line.split_whitespace().collect::<Vec<_>>();
+                if version < 3 {
+                    parts.insert(5.min(parts.len()), "0");
+                }
+                // Version 4 had no damage or armor
+                if version < 5 {
+                    parts.splice(6.min(parts.len())..6.min(parts.len()), ["0", "0"]);
+                }
+                let (unit, offset) = match parts.as_slice() {
+                    [unit_type, jester_type, team, x, y, captures, damage, armor, offset @ ..] if offset.is_empty() || offset.len() == 2 => {
+                        let unit = Unit {
+                            unit_type: unit_type.parse()?,
+                            jester_type: jester_type.parse()?,
+                            team: parse_enum(team)?,
+                            pos: ivec2(parse_number(x)?, parse_number(y)?),
+                            captures: parse_number(captures)?,
+                            damage: parse_number(damage)?,
+                            armor: parse_number(armor)?,
+                        };
+                        (unit, offset)
+                    }
+                    _ => return Err("expected unit type, jester type, team, position, captures, damage, armor and optional offset".to_owned()),
+                };
+                // Units in the shop stand on its 5x5 board
+                let inside = if is_shopping { unit.pos.cmpge(IVec2::ZERO).all() && unit.pos.cmplt(IVec2::splat(5)).all() } else { tilemap.is_inside(unit.pos) };
+                if !inside {
+                    return Err(format!("position {} {} is outside the board", unit.pos.x, unit.pos.y));
+                }
+                if unit.jester_type == UnitType::Jester {
+                    return Err("jester type can't be Jester".to_owned());
+                }
+                if unit.damage >= unit.unit_type.def().hp {
+                    return Err(format!("damage {} is not less than the hp of a {:?}", unit.damage, unit.unit_type));
+                }
+                if unit.armor > MAX_ARMOR {
+                    return Err(format!("armor must be at most {MAX_ARMOR}, not {}", unit.armor));
+                }
+                let initial = match offset {
+                    [x, y] => Some(InitialPosition { offset: IVec2::new(parse_number(x)?, parse_number(y)?) }),
+                    _ => None,
+                };
+                Ok((unit, initial))
+            };
+            units.push(parse_unit().map_err(|e| format!("line {line_number}: {e}"))?);
+        }
+
+        Ok(SaveData {
+            seed: seed.unwrap_or(rng_seed),
+            floor,
+            material,
+            rng_seed,
+            player_turn,
+            is_shopping,
+            difficulty,
+            engine,
+            predictable,
+            classic_rules,
+            hit_points,
+            hints_left,
+            tilemap,
+            start_room,
+            units,
+        })
+    }
+}
+
+pub fn write_save(data: &SaveData) -> Result<(), String> {
+    std::fs::write(SAVE_PATH, data.to_string()).map_err(|e| e.to_string())
+}
+
+pub fn read_save() -> Result<SaveData, String> {
+    let text = std::fs::read_to_string(SAVE_PATH).map_err(|e| e.to_string())?;
+    SaveData::from_text(&text)
+}
+
+pub fn has_save() -> bool {
+    std::path::Path::new(SAVE_PATH).exists()
+}
+
+pub fn delete_save() {
+    let _ = std::fs::remove_file(SAVE_PATH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_round_trip_test() {
+        let map_plan = [" .#.", " .<."];
+        let data = SaveData {
+            seed: 123456789,
+            floor: 3,
+            material: 17,
+            rng_seed: u64::MAX,
+            player_turn: false,
+            is_shopping: true,
+            difficulty: Difficulty::Brutal,
+            engine: EngineKind::Mcts,
+            predictable: true,
+            classic_rules: true,
+            hit_points: true,
+            hints_left: 2,
+            tilemap: TileMap::from(&map_plan[..]),
+            start_room: Some((ivec2(0, 0), ivec2(2, 1))),
+            units: vec![
+                (Unit { pos: ivec2(1, 0), unit_type: UnitType::King, jester_type: UnitType::Rook, team: Team::Player, captures: 0, damage: 0, armor: 1 }, Some(InitialPosition { offset: ivec2(0, 0) })),
+                (Unit { pos: ivec2(3, 1), unit_type: UnitType::Jester, jester_type: UnitType::Knight, team: Team::Ai, captures: 0, damage: 0, armor: 0 }, None),
+                (Unit { pos: ivec2(2, 1), unit_type: UnitType::Pawn, jester_type: UnitType::Rook, team: Team::Player, captures: 2, damage: 0, armor: 0 }, Some(InitialPosition { offset: ivec2(1, 1) })),
+                (Unit { pos: ivec2(0, 1), unit_type: UnitType::Queen, jester_type: UnitType::Rook, team: Team::Ai, captures: 0, damage: 1, armor: 2 }, None),
+            ],
+        };
+
+        let text = data.to_string();
+        let loaded = SaveData::from_text(&text).unwrap();
+        assert_eq!(loaded.to_string(), text);
+        assert_eq!(loaded.tilemap.to_strings(), map_plan);
+        assert_eq!(loaded.units[1].0, data.units[1].0);
+        assert!(loaded.units[1].1.is_none());
+
+        let broken = text.replace("Jester", "Joker");
+        assert!(SaveData::from_text(&broken).err().unwrap().contains("unknown value 'Joker'"));
+        let broken = text.replace("Ai 3 1 0", "Ai 3 5 0");
+        assert!(SaveData::from_text(&broken).err().unwrap().ends_with("position 3 5 is outside the board"));
+        let broken = text.replace("Jester Knight", "Jester Jester");
+        assert!(SaveData::from_text(&broken).err().unwrap().ends_with("jester type can't be Jester"));
+        let broken = text.replace("Ai 0 1 0 1 2", "Ai 0 1 0 2 2");
+        assert!(SaveData::from_text(&broken).err().unwrap().ends_with("damage 2 is not less than the hp of a Queen"));
+        let broken = text.replace("Ai 0 1 0 1 2", "Ai 0 1 0 1 4");
+        assert!(SaveData::from_text(&broken).err().unwrap().ends_with("armor must be at most 3, not 4"));
+        assert_eq!(loaded.start_room, data.start_room);
+        assert_eq!(loaded.units[2], data.units[2]);
+        assert_eq!(loaded.units[3], data.units[3]);
+
+        // Saves of older versions are still loaded
+        let version_4 = text
+            .replace("kings_conquest_save 5", "kings_conquest_save 4")
+            .replace("hit_points 1\n", "")
+            .replace("King Rook Player 1 0 0 0 1", "King Rook Player 1 0 0")
+            .replace("Ai 3 1 0 0 0", "Ai 3 1 0")
+            .replace("Player 2 1 2 0 0", "Player 2 1 2")
+            .replace("Ai 0 1 0 1 2", "Ai 0 1 0");
+        let loaded = SaveData::from_text(&version_4).unwrap();
+        assert!(loaded.classic_rules && !loaded.hit_points);
+        assert_eq!(loaded.units[0].0.armor, 0);
+        assert_eq!(loaded.units[3].0.damage, 0);
+        assert_eq!(loaded.units[2], data.units[2]);
+        let version_3 = version_4.replace("kings_conquest_save 4", "kings_conquest_save 3").replace("classic_rules 1\n", "");
+        let loaded = SaveData::from_text(&version_3).unwrap();
+        assert!(!loaded.classic_rules);
+        assert_eq!(loaded.units[2], data.units[2]);
+        let version_2 = version_3
+            .replace("kings_conquest_save 3", "kings_conquest_save 2")
+            .replace("start_room 0 0 2 1\n", "")
+            .replace("Player 1 0 0 0 0", "Player 1 0 0 0")
+            .replace("Ai 3 1 0", "Ai 3 1")
+            .replace("Ai 0 1 0", "Ai 0 1")
+            .replace("Player 2 1 2 1 1", "Player 2 1 1 1");
+        let loaded = SaveData::from_text(&version_2).unwrap();
+        assert_eq!(loaded.start_room, None);
+        assert_eq!(loaded.units[2].0.captures, 0);
+        assert_eq!(loaded.units[1], data.units[1]);
+        let old = version_2.replace("kings_conquest_save 2", "kings_conquest_save 1").replace("seed 123456789\n", "");
+        assert_eq!(SaveData::from_text(&old).unwrap().seed, u64::MAX);
+        assert!(SaveData::from_text("kings_conquest_save 999").err().unwrap().contains("version"));
+    }
+}
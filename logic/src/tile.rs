@@ -1,4 +1,4 @@
-use macroquad::prelude::*;
+use glam::{IVec2, ivec2};
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Tile {
@@ -8,15 +8,6 @@ pub enum Tile {
     Stairs,
 }
 
-pub fn get_tile_color(tile: Tile) -> &'static Color {
-    match tile {
-        Tile::Empty => &BLACK,
-        Tile::Floor => &WHITE,
-        Tile::Wall => &DARKPURPLE,
-        Tile::Stairs => &WHITE,
-    }
-}
-
 pub fn is_passable(tile: Tile) -> bool {
     matches!(tile, Tile::Floor | Tile::Stairs)
 }
@@ -76,6 +67,13 @@ impl TileMap {
         }
     }
 
+    // Inverse of TileMap::from
+    pub fn to_strings(&self) -> Vec<String> {
+        (0..self.height)
+            .map(|y| (0..self.width).map(|x| tile_to_chr(self.get_unchecked(ivec2(x as i32, y as i32)))).collect())
+            .collect()
+    }
+
     pub fn find_tile(&self, tile_type: Tile) -> Option<IVec2> {
         for y in 0..self.height {
             for x in 0..self.width {
@@ -89,16 +87,26 @@ impl TileMap {
     }
 }
 
+pub fn chr_to_tile(chr: char) -> Tile {
+    match chr {
+        '#' => Tile::Wall,
+        '.' => Tile::Floor,
+        '<' => Tile::Stairs,
+        _ => Tile::Empty,
+    }
+}
+
+pub fn tile_to_chr(tile: Tile) -> char {
+    match tile {
+        Tile::Wall => '#',
+        Tile::Floor => '.',
+        Tile::Stairs => '<',
+        Tile::Empty => ' ',
+    }
+}
+
 impl From<&[&str]> for TileMap {
     fn from(arr: &[&str]) -> Self {
-        fn chr_to_tile(chr: char) -> Tile {
-            match chr {
-                '#' => Tile::Wall,
-                '.' => Tile::Floor,
-                '<' => Tile::Stairs,
-                _ => Tile::Empty,
-            }
-        }
         let mut tilemap = TileMap::new(arr[0].len(), arr.len());
         for (y, line) in arr.iter().enumerate() {
             for (x, chr) in line.chars().enumerate() {
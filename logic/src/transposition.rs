@@ -0,0 +1,59 @@
+use crate::boardstate::Move;
+
+const DEFAULT_SIZE: usize = 1 << 16;
+
+// Key mixed into the board hash when the player is the side to move
+pub const PLAYER_TO_MOVE_KEY: u64 = 0x3c6ef372fe94f82b;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub key: u64,
+    pub depth: u32,
+    pub value: f32,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+}
+
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        TranspositionTable::new(DEFAULT_SIZE)
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(size: usize) -> Self {
+        TranspositionTable { entries: vec![None; size.max(1)] }
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    pub fn probe(&self, key: u64) -> Option<&Entry> {
+        self.entries[self.index(key)].as_ref().filter(|e| e.key == key)
+    }
+
+    pub fn store(&mut self, entry: Entry) {
+        let index = self.index(entry.key);
+        let slot = &mut self.entries[index];
+        // Keep the deeper search result when the same position is stored again
+        let replace = match slot {
+            Some(old) => old.key != entry.key || old.depth <= entry.depth,
+            None => true,
+        };
+        if replace {
+            *slot = Some(entry);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::{fmt, str::FromStr};
+use glam::IVec2;
+use enum_iterator::Sequence;
+
+use crate::piece::{PIECES, PieceDef};
+
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash, Sequence)]
+pub enum Team {
+    #[default] Player,
+    Ai,
+}
+
+impl Team {
+    pub fn opponent(self) -> Team {
+        match self {
+            Team::Player => Team::Ai,
+            Team::Ai => Team::Player,
+        }
+    }
+}
+
+// Index of a piece in pieces.txt
+#[derive(Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UnitType(pub(crate) u8);
+
+// Pieces that the rules refer to, in the order of pieces.txt
+#[allow(non_upper_case_globals)]
+impl UnitType {
+    pub const Pawn: UnitType = UnitType(0);
+    pub const Knight: UnitType = UnitType(1);
+    pub const King: UnitType = UnitType(2);
+    pub const Bishop: UnitType = UnitType(3);
+    pub const Jester: UnitType = UnitType(4);
+    pub const Rook: UnitType = UnitType(5);
+    pub const Queen: UnitType = UnitType(6);
+    pub const Archbishop: UnitType = UnitType(7);
+}
+
+impl UnitType {
+    pub fn all() -> impl Iterator<Item = UnitType> {
+        (0..PIECES.len() as u8).map(UnitType)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn def(self) -> &'static PieceDef {
+        &PIECES[self.index()]
+    }
+}
+
+impl fmt::Debug for UnitType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.def().name)
+    }
+}
+
+impl FromStr for UnitType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        UnitType::all().find(|t| t.def().name == s).ok_or_else(|| format!("unknown value '{s}'"))
+    }
+}
+
+// Player units that can promote may do so with their capture that reaches this count, or any later one
+pub const PROMOTION_CAPTURES: u8 = 3;
+
+// Armor is bought in the shop one point at a time, each point stops one hit
+pub const MAX_ARMOR: u8 = 3;
+pub const ARMOR_PRICE: i32 = 2;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Unit {
+    pub pos: IVec2,
+    pub unit_type: UnitType,
+    pub jester_type: UnitType,
+    pub team: Team,
+    // Enemies captured on this floor, counted up to PROMOTION_CAPTURES for units that can promote
+    pub captures: u8,
+    // Hits taken on this floor, the unit is captured when they reach its hp
+    pub damage: u8,
+    pub armor: u8,
+}
+
+impl Unit {
+    pub fn convert_jester(&mut self, captured_unit: Unit) {
+        self.jester_type =
+            if captured_unit.unit_type == UnitType::Jester {
+                captured_unit.jester_type
+            }
+            else {
+                captured_unit.unit_type
+            };
+        assert!(self.jester_type != UnitType::Jester);
+    }
+
+    pub fn can_promote(&self) -> bool {
+        self.unit_type.def().promotion.is_some()
+    }
+
+    pub fn hp(&self) -> u8 {
+        self.unit_type.def().hp - self.damage
+    }
+
+    // Whether a capture of the unit only takes one point of armor or hp
+    pub fn survives_hit(&self) -> bool {
+        self.armor > 0 || self.hp() > 1
+    }
+
+    pub fn take_hit(&mut self) {
+        if self.armor > 0 {
+            self.armor -= 1;
+        }
+        else {
+            self.damage += 1;
+        }
+    }
+}
+
+pub fn is_enemy(unit: &Unit, other_unit: &Unit) -> bool {
+    unit.team != other_unit.team
+}
+
+pub fn material_reward(unit_type: UnitType) -> i32 {
+    unit_type.def().reward
+}
+
+pub fn unit_buy_price(unit_type: UnitType) -> i32 {
+    unit_type.def().price
+}
+
+// Material the player pays for promoting a unit, units are never promoted for profit
+pub fn promotion_cost(from: UnitType, to: UnitType) -> i32 {
+    (unit_buy_price(to) - unit_buy_price(from)).max(0)
+}
+
+pub fn unit_description(unit_type: UnitType) -> &'static str {
+    &unit_type.def().description
+}
@@ -0,0 +1,9 @@
+use glam::IVec2;
+
+pub fn dist(v1: &IVec2, v2: &IVec2) -> f32 {
+    f32::sqrt(dist2(v1, v2))
+}
+
+pub fn dist2(v1: &IVec2, v2: &IVec2) -> f32 {
+    ((v1.x - v2.x) * (v1.x - v2.x) + (v1.y - v2.y) * (v1.y - v2.y)) as f32
+}
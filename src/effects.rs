@@ -2,7 +2,9 @@ use hecs::{World, CommandBuffer};
 use macroquad::prelude::*;
 use ::rand::{rngs::SmallRng, Rng, SeedableRng};
 
-use crate::{graphics::{Graphics, TILE_SIZEF}, unit::Team};
+use chess_logic::unit::Team;
+
+use crate::graphics::{Graphics, TILE_SIZEF};
 
 struct Lifetime(f32);
 
@@ -1,8 +1,7 @@
 use std::{collections::HashMap, default::default};
 use macroquad::prelude::*;
-use enum_iterator::{Sequence, all};
 
-use crate::unit::{Unit, Team, UnitType};
+use chess_logic::{tile::Tile, unit::{Unit, Team, UnitType}};
 
 pub const TILE_SIZE: usize = 20;
 pub const TILE_SIZEF: f32 = TILE_SIZE as f32;
@@ -18,62 +17,47 @@ pub struct MouseInfo {
     pub tile: IVec2,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Sequence)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Sprite {
-    Horse(bool),
-    Pawn(bool),
-    Bishop(bool),
-    Jester(bool),
-    Rook(bool),
-    King(bool),
-    Queen(bool),
-    Archbishop(bool),
+    Unit(UnitType, bool),
     TileLight,
     TileDark,
     Stairs,
     Shadow,
 }
 
-fn sprite_texture_name(sprite: Sprite) -> &'static str {
+fn all_sprites() -> Vec<Sprite> {
+    let mut sprites = vec![Sprite::TileLight, Sprite::TileDark, Sprite::Stairs, Sprite::Shadow];
+    for unit_type in UnitType::all() {
+        sprites.push(Sprite::Unit(unit_type, false));
+        sprites.push(Sprite::Unit(unit_type, true));
+    }
+    sprites
+}
+
+fn sprite_texture_name(sprite: Sprite) -> String {
     match sprite {
-        Sprite::Horse(false) => "horse_red_8",
-        Sprite::Pawn(false) => "pawn_red_8",
-        Sprite::Bishop(false) => "bishop_red_8",
-        Sprite::Jester(false) => "jester_red_8",
-        Sprite::Rook(false) => "rook_red_8",
-        Sprite::King(false) => "king_red_8",
-        Sprite::Queen(false) => "queen_red_8",
-        Sprite::Archbishop(false) => "archbishop_red_8",
-
-        Sprite::Horse(true) => "horse_blue_8",
-        Sprite::Pawn(true) => "pawn_blue_8",
-        Sprite::Bishop(true) => "bishop_blue_8",
-        Sprite::Jester(true) => "jester_blue_8",
-        Sprite::Rook(true) => "rook_blue_8",
-        Sprite::King(true) => "king_blue_8",
-        Sprite::Queen(true) => "queen_blue_8",
-        Sprite::Archbishop(true) => "archbishop_blue_8",
-
-        Sprite::TileLight => "light",
-        Sprite::TileDark => "dark",
-        Sprite::Stairs => "stairs",
-        Sprite::Shadow => "shadow",
+        Sprite::Unit(unit_type, player) => format!("{}_{}_8", unit_type.def().sprite, if player { "blue" } else { "red" }),
+        Sprite::TileLight => "light".to_owned(),
+        Sprite::TileDark => "dark".to_owned(),
+        Sprite::Stairs => "stairs".to_owned(),
+        Sprite::Shadow => "shadow".to_owned(),
     }
 }
 
-pub fn unit_into_sprite(unit: &Unit) -> Sprite {
-    match unit.unit_type {
-        UnitType::Pawn => Sprite::Pawn(unit.team == Team::Player),
-        UnitType::Knight => Sprite::Horse(unit.team == Team::Player),
-        UnitType::King => Sprite::King(unit.team == Team::Player),
-        UnitType::Bishop => Sprite::Bishop(unit.team == Team::Player),
-        UnitType::Jester => Sprite::Jester(unit.team == Team::Player),
-        UnitType::Rook => Sprite::Rook(unit.team == Team::Player),
-        UnitType::Queen => Sprite::Queen(unit.team == Team::Player),
-        UnitType::Archbishop => Sprite::Archbishop(unit.team == Team::Player),
+pub fn get_tile_color(tile: Tile) -> &'static Color {
+    match tile {
+        Tile::Empty => &BLACK,
+        Tile::Floor => &WHITE,
+        Tile::Wall => &DARKPURPLE,
+        Tile::Stairs => &WHITE,
     }
 }
 
+pub fn unit_into_sprite(unit: &Unit) -> Sprite {
+    Sprite::Unit(unit.unit_type, unit.team == Team::Player)
+}
+
 fn extract_count_from_path(path: &str) -> Option<usize> {
     let mut parts = path.split('.');
     let num_str = parts.next()?.split('_').last()?;
@@ -129,7 +113,7 @@ pub struct Graphics {
 impl Graphics {
     pub async fn new() -> Self {
         let mut textures = HashMap::new();
-        for spr in all::<Sprite>() {
+        for spr in all_sprites() {
             let texture_path = sprite_texture_name(spr);
             textures.insert(spr, Graphics::load_tex(format!("assets/{texture_path}.png").as_str()).await);
         }
@@ -171,12 +155,31 @@ impl Graphics {
         );
     }
 
+    pub fn tint_square(&self, (offset_x, offset_y): (f32, f32), p: &IVec2, color: Color) {
+        draw_rectangle(
+            offset_x + p.x as f32 * TILE_SIZEF,
+            offset_y + p.y as f32 * TILE_SIZEF,
+            TILE_SIZEF, TILE_SIZEF,
+            color
+        );
+    }
+
     pub fn highlight_square(&self, (offset_x, offset_y): (f32, f32), p: &IVec2, color: Color) {
         draw_rectangle_lines(offset_x - 1.0 + p.x as f32 * TILE_SIZEF,
                              offset_y - 1.0 + p.y as f32 * TILE_SIZEF,
                              TILE_SIZEF + 2.0, TILE_SIZEF + 2.0, 4.0, color);
     }
 
+    pub fn draw_arrow(&self, (offset_x, offset_y): (f32, f32), from: &IVec2, to: &IVec2, color: Color) {
+        let center = |p: &IVec2| vec2(offset_x + (p.x as f32 + 0.5) * TILE_SIZEF, offset_y + (p.y as f32 + 0.5) * TILE_SIZEF);
+        let (start, end) = (center(from), center(to));
+        let dir = (end - start).normalize_or_zero();
+        let side = vec2(-dir.y, dir.x) * 4.0;
+        let base = end - dir * 6.0;
+        draw_line(start.x, start.y, base.x, base.y, 2.0, color);
+        draw_triangle(end, base + side, base - side, color);
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn draw_tex_ex(&self, texture: Texture2D, x: f32, y: f32, w: f32, h: f32, rotation: f32, color: Color, outline: bool) {
         if outline {
@@ -2,12 +2,7 @@
 #![feature(default_free_fn)]
 #![feature(inline_const)]
 mod graphics;
-mod unit;
-mod tile;
-mod evaluation;
 mod utils;
-mod boardstate;
-mod mapgenerator;
 mod effects;
 mod sound;
 
@@ -15,20 +10,14 @@ use hecs::{World, Entity, CommandBuffer};
 use macroquad::prelude::*;
 use once_cell::sync::Lazy;
 use ::rand::{rngs::SmallRng, SeedableRng, Rng};
-use std::{default::default, borrow::Cow};
+use std::default::default;
 use glam::i32::ivec2;
-use unit::*;
-use tile::*;
+use chess_logic::{unit::*, tile::*, evaluation::*, boardstate::*, aiworker::*, difficulty::*, engine::*, savegame::*, position::Position, replay::*, run::*};
 use graphics::*;
-use evaluation::*;
-use boardstate::*;
-use mapgenerator::*;
 use sound::*;
 
 const SCREEN_SIZE: Vec2 = Vec2::new(384.0, 384.0);
 
-const LAST_FLOOR: usize = 12;
-
 fn draw_board(graphics: &Graphics, Vec2 { x: offset_x, y: offset_y }: Vec2, tilemap: &TileMap) {
     for y in 0..tilemap.get_height() {
         for x in 0..tilemap.get_width() {
@@ -50,7 +39,7 @@ fn draw_board(graphics: &Graphics, Vec2 { x: offset_x, y: offset_y }: Vec2, tile
     }
 }
 
-fn draw_units(graphics: &Graphics, Vec2 { x: offset_x, y: offset_y }: Vec2, world: &World) {
+fn draw_units(graphics: &Graphics, Vec2 { x: offset_x, y: offset_y }: Vec2, world: &World, hit_points: bool) {
     let char_sprite_index = CHARACTER_ANIM_INDICES[(graphics.time * 16.0) as usize % CHARACTER_ANIM_INDICES.len()];
     let mut q = world.query::<(&Vec3, &Unit)>();
     let mut units = q.iter().map(|(_, data)| data).collect::<Vec<_>>();
@@ -58,12 +47,26 @@ fn draw_units(graphics: &Graphics, Vec2 { x: offset_x, y: offset_y }: Vec2, worl
     for (Vec3 { x, y, z }, unit) in units.into_iter() {
         graphics.draw_sprite_ex(Sprite::Shadow, offset_x + x, offset_y + y + 27.0, 16.0, 8.0, &BLACK, false);
         graphics.draw_sprite(unit_into_sprite(unit), char_sprite_index, offset_x + x, offset_y + y + z, &WHITE, true);
+
+        // Pips below the unit for armor points and hit points of units that have more than one
+        let hp_pips = if hit_points && unit.unit_type.def().hp > 1 { unit.hp() } else { 0 };
+        let pips = (0..unit.armor).map(|_| LIGHTGRAY).chain((0..hp_pips).map(|_| RED));
+        for (i, col) in pips.enumerate() {
+            draw_rectangle(offset_x + x + 2.0 + 4.0 * i as f32, offset_y + y + 31.0, 3.0, 3.0, col);
+        }
     }
 }
 
-#[derive(Default, Clone, Copy)]
-struct InitialPosition {
-    offset: IVec2, // Offset in relation to player's king
+// Unit name with its hit points and armor when it has any to speak of
+fn unit_status(unit: &Unit, hit_points: bool) -> String {
+    let mut text = format!("{:?}", unit.unit_type);
+    if hit_points && unit.unit_type.def().hp > 1 {
+        text += &format!(", hp {}", unit.hp());
+    }
+    if unit.armor > 0 {
+        text += &format!(", armor {}", unit.armor);
+    }
+    text
 }
 
 #[derive(Default, Clone, Copy)]
@@ -80,15 +83,24 @@ struct UnitAnimation {
     duration: f64,
     unit_move: Move,
     captured_unit: Option<Entity>,
+    // Progress at which the captured unit is removed, before the end when it is jumped over
+    capture_progress: f32,
+    jump_height: f32,
+    // Captured unit after the hit when it survives, the moving unit then bounces back
+    hit: Option<Unit>,
 }
 impl UnitAnimation {
-    fn new(m: Move, captured_unit: Option<Entity>) -> Self {
-        let duration = 0.2 + 0.1 * utils::dist(&m.from, &m.to) as f64;
+    fn new(m: Move, captured_unit: Option<Entity>, jump_height: f32) -> Self {
+        let distance = chess_logic::utils::dist(&m.from, &m.to);
+        let duration = 0.2 + 0.1 * distance as f64;
         UnitAnimation {
             unit_move: m,
             start_time: macroquad::time::get_time(),
             duration,
             captured_unit,
+            capture_progress: m.capture.map_or(1.0, |pos| chess_logic::utils::dist(&m.from, &pos) / distance),
+            jump_height,
+            hit: None,
         }
     }
 }
@@ -103,58 +115,108 @@ impl ShopState {
     }
 }
 
+// Short enough to type in the menu
+fn random_seed() -> u64 {
+    let time = u64::from_be_bytes(macroquad::time::get_time().to_be_bytes());
+    splitmix64(time) % 1_000_000_000
+}
+
 struct GameState {
-    rng: SmallRng,
-    material: i32,
+    run: Run,
+    // Camera shake and other effects, kept apart so that they don't affect gameplay
+    cosmetic_rng: SmallRng,
     board_offset: Vec2,
-    player_turn: bool,
-    tilemap: TileMap,
     world: World,
     valid_moves_for_selected_unit: Vec<Move>,
+    // Moves to the same square that the player picks between when the unit can promote
+    promotion_moves: Vec<Move>,
     highlighted_moves: Vec<Move>,
     highlighted_unit: Unit,
-    is_shopping: bool,
     win_timer: Option<f32>,
     gameover_timer: Option<f32>,
-    floor: usize,
-    last_gen_result: Option<MapGeneratorResult>,
     shop_state: ShopState,
     camera_shake: f32,
+    ai_search: Option<AiSearch>,
+    hint_search: Option<AiSearch>,
+    hint: Option<Move>,
+    intent_search: Option<AiSearch>,
+    intent: Option<Move>,
+    ai_last_search: SearchResult,
+    debug_overlay: bool,
+    threat_overlay: bool,
+    // Updated after every move, shown whether or not the rules are classic
+    kings_in_check: Vec<Team>,
 }
 
 impl GameState {
-    fn new() -> Self {
-        let seed = u64::from_be_bytes(macroquad::time::get_time().to_be_bytes());
-        GameState {
-            rng: SmallRng::seed_from_u64(seed),
-            material: 20,
+    fn new(run: Run) -> Self {
+        let mut gamestate = GameState {
+            run,
+            cosmetic_rng: SmallRng::seed_from_u64(random_seed()),
             board_offset: Vec2::new(42.0, 70.0),
-            player_turn: true,
-            tilemap: TileMap::new(1, 1),
             world: World::new(),
             valid_moves_for_selected_unit: default(),
+            promotion_moves: default(),
             highlighted_moves: default(),
             highlighted_unit: default(),
-            is_shopping: false,
             win_timer: None,
             gameover_timer: None,
-            floor: 0,
-            last_gen_result: None,
             shop_state: ShopState::new(),
             camera_shake: 0.0,
+            ai_search: None,
+            hint_search: None,
+            hint: None,
+            intent_search: None,
+            intent: None,
+            ai_last_search: default(),
+            debug_overlay: false,
+            threat_overlay: false,
+            kings_in_check: vec![],
+        };
+        gamestate.spawn_units();
+        gamestate
+    }
+
+    fn from_save_data(data: SaveData) -> Self {
+        let replay = match read_replay() {
+            Ok(replay) if replay.seed == data.seed => Some(replay),
+            _ => {
+                miniquad::warn!("Replay of the saved run is missing, the replay will be incomplete");
+                None
+            }
+        };
+        GameState::new(Run::from_save_data(data, replay))
+    }
+
+    fn save(&mut self) {
+        if let Err(e) = self.run.save() {
+            miniquad::error!("Failed to save the game: {}", e);
+        }
+    }
+
+    // Starts the game over or win screen once the run has been decided
+    fn finish_run(&mut self, delay: f32) {
+        match self.run.outcome {
+            Some(Outcome::Won) => self.win_timer = Some(delay),
+            Some(Outcome::Lost) => self.gameover_timer = Some(delay),
+            None => return,
+        }
+        if let Err(e) = self.run.end_run() {
+            miniquad::error!("Failed to write the replay: {}", e);
         }
     }
 
     fn get_valid_moves(&self, entity: Entity) -> Vec<Move> {
         let mut moves = vec![];
         if let Some(selected_unit) = self.world.query_one::<&Unit>(entity).unwrap().get() {
-            moves = self.get_boardstate().get_valid_moves_for_unit(selected_unit);
+            moves = self.run.board_state().get_valid_moves_for_unit(selected_unit);
         }
         moves
     }
 
     fn select_unit(&mut self, selection: Option<Entity>) {
         self.valid_moves_for_selected_unit.clear();
+        self.promotion_moves.clear();
         utils::delete_all_components::<SelectedUnit>(&mut self.world);
 
         if let Some(entity) = selection {
@@ -167,74 +229,178 @@ impl GameState {
         self.world.query::<(&Unit, &SelectedUnit)>().iter().next().map(|(e, (u, s))| (e, *u, *s))
     }
 
-    fn add_unit(&mut self, pos: IVec2, unit_type: UnitType, team: Team, offset: Option<InitialPosition>) {
-        let unit_entity = self.world.spawn((
-            Vec3::ZERO,
-            Unit { pos, unit_type, jester_type: UnitType::Rook, team },
-            UnitAnimation::new(Move { from: pos, to: pos }, None)
-        ));
-        if let Some(offset) = offset {
-            assert!(self.world.insert_one(unit_entity, offset).is_ok());
+    fn update_kings_in_check(&mut self) {
+        self.kings_in_check.clear();
+        if !self.run.is_shopping {
+            let state = self.run.board_state();
+            self.kings_in_check.extend([Team::Player, Team::Ai].into_iter().filter(|team| state.in_check(*team)));
         }
     }
 
-    fn get_boardstate(&self) -> BoardState {
-        let stairs = self.tilemap.find_tile(Tile::Stairs);
-        BoardState {
-            tilemap: Cow::Borrowed(&self.tilemap),
-            units: self.world.query::<&Unit>().iter().map(|(_, u)| *u).collect::<Vec<_>>(),
-            stairs,
+    // Replaces the unit entities with the units of the run
+    fn spawn_units(&mut self) {
+        self.update_kings_in_check();
+        let entities = self.world.query::<&Unit>().iter().map(|(e, _)| e).collect::<Vec<_>>();
+        for entity in entities {
+            let _ = self.world.despawn(entity);
+        }
+        for &(unit, _) in &self.run.units {
+            self.world.spawn((
+                Vec3::ZERO,
+                unit,
+                UnitAnimation::new(Move { from: unit.pos, to: unit.pos, ..default() }, None, 5.0)
+            ));
         }
     }
 
+    // Starts the AI search on the first call and makes the move once the search has finished
     fn make_ai_move(&mut self) {
-        assert!(!self.player_turn);
-        let state = self.get_boardstate();
-        let eval = Evaluation::from_gamestate(state);
-        if let Some(next_move) = eval.minimax(5, f32::MIN, f32::MAX, false, &mut vec![]).0 {
-            let (entity, unit) = self.get_unit_at(&next_move.from).unwrap();
-            assert_eq!(unit.team, Team::Ai);
-            self.make_move(entity, &next_move);
+        assert!(!self.run.player_turn);
+        self.intent_search = None;
+        if let Some(intent) = self.intent.take() {
+            // Predictable AI keeps its telegraphed move unless the player made it impossible
+            if self.run.predictable && self.run.board_state().get_valid_moves(Team::Ai).contains(&intent) {
+                self.apply_ai_move(Some(intent));
+                return
+            }
+        }
+
+        let result = match &self.ai_search {
+            Some(search) => search.poll(),
+            None => {
+                let state = self.run.board_state();
+                let settings = self.run.ai_search_settings();
+                self.ai_search = Some(AiSearch::start(state, Team::Ai, self.run.engine, settings));
+                None
+            }
+        };
+
+        if let Some(result) = result {
+            self.ai_search = None;
+            self.ai_last_search = result;
+            let next_move = self.run.choose_ai_move(result.best_move);
+            self.apply_ai_move(next_move);
         }
-        self.player_turn = true;
     }
 
-    fn make_player_move(&mut self, entity: Entity, m: &Move) {
-        assert!(self.player_turn);
-        self.make_move(entity, m);
-        self.player_turn = false;
+    fn apply_ai_move(&mut self, m: Option<Move>) {
+        self.run.apply_ai_move(m);
+        if let Some(m) = m {
+            self.animate_move(&m);
+        }
     }
 
-    fn get_unit_at(&self, tile_pos: &IVec2) -> Option<(Entity, Unit)> {
-        let mut q = self.world.query::<&Unit>();
-        let x = q.iter().find(|(_, u)| u.pos == *tile_pos);
-        x.map(|(e, u)| (e, *u))
+    // Searches the best move for the player, uses up one hint
+    fn request_hint(&mut self) {
+        if self.hint_search.is_some() || self.hint.is_some() {
+            return
+        }
+        if let Some(settings) = self.run.use_hint() {
+            let state = self.run.board_state();
+            self.hint_search = Some(AiSearch::start(state, Team::Player, self.run.engine, settings));
+        }
     }
 
-    fn make_move(&mut self, entity: Entity, m: &Move) {
-        let captured = self.get_unit_at(&m.to);
-        let captured_entity = captured.map(|(e, _)| e);
-        let captured_unit = captured.map(|(_, u)| u);
+    fn update_hint(&mut self) {
+        if let Some(result) = self.hint_search.as_ref().and_then(|search| search.poll()) {
+            self.hint_search = None;
+            self.hint = result.best_move;
+        }
+    }
 
-        let mut unit = self.world.query_one_mut::<&mut Unit>(entity).unwrap();
-        unit.pos = m.to;
-        if let Some(captured_unit) = captured_unit {
-            if unit.unit_type == UnitType::Jester {
-                unit.convert_jester(captured_unit);
+    // Searches the move the AI would make in the current position so it can be shown to the player
+    fn update_intent(&mut self) {
+        if let Some(search) = &self.intent_search {
+            if let Some(result) = search.poll() {
+                self.intent_search = None;
+                self.intent = result.best_move;
             }
-            if captured_unit.team == Team::Ai {
-                self.material += material_reward(captured_unit.unit_type);
+        }
+        else if self.intent.is_none() {
+            let state = self.run.board_state();
+            if state.get_valid_moves(Team::Ai).is_empty() {
+                return
             }
+            let settings = self.run.intent_search_settings();
+            self.intent_search = Some(AiSearch::start(state, Team::Ai, self.run.engine, settings));
+        }
+    }
 
-            if captured_unit.unit_type == UnitType::King && captured_unit.team == Team::Player {
-                self.gameover_timer = Some(4.0);
-            }
+    fn clear_intent(&mut self) {
+        self.intent_search = None;
+        self.intent = None;
+    }
 
-            if captured_unit.unit_type == UnitType::King && captured_unit.team == Team::Ai {
-                self.win_timer = Some(4.0);
-            }
+    fn clear_hint(&mut self) {
+        self.hint_search = None;
+        self.hint = None;
+    }
+
+    fn clear_searches(&mut self) {
+        self.ai_search = None;
+        self.clear_hint();
+        self.clear_intent();
+    }
+
+    fn make_player_move(&mut self, m: &Move) {
+        self.clear_hint();
+        // Intent is kept for the predictable AI, but a search from the old position is useless
+        self.intent_search = None;
+        self.run.make_player_move(m);
+        self.animate_move(m);
+    }
+
+    fn give_up(&mut self) {
+        self.clear_searches();
+        self.run.give_up();
+        self.show_king_lost();
+    }
+
+    // Removes the player's king that is no longer in the run
+    fn show_king_lost(&mut self) {
+        let king = self.world.query::<&Unit>().iter().find(|(_, u)| u.unit_type == UnitType::King && u.team == Team::Player).map(|(e, u)| (e, *u));
+        if let Some((entity, unit)) = king {
+            let _ = self.world.despawn(entity);
+            self.finish_run(1.0);
+
+            let pos = utils::tile_pos_to_pixels(&unit.pos) + self.board_offset;
+            let mut cmd = CommandBuffer::new();
+            effects::add_unit_capture_particles(&mut cmd, pos, unit.team);
+            cmd.run_on(&mut self.world);
         }
-        assert!(self.world.insert_one(entity, UnitAnimation::new(*m, captured_entity)).is_ok());
+    }
+
+    fn get_unit_at(&self, tile_pos: &IVec2) -> Option<(Entity, Unit)> {
+        let mut q = self.world.query::<&Unit>();
+        let x = q.iter().find(|(_, u)| u.pos == *tile_pos);
+        x.map(|(e, u)| (e, *u))
+    }
+
+    // Shows a move that has been made in the run, the entities are still where they were before it
+    fn animate_move(&mut self, m: &Move) {
+        let (entity, _) = self.get_unit_at(&m.from).unwrap();
+        let captured_entity = self.get_unit_at(&m.capture_pos()).map(|(e, _)| e);
+        // Leaps and moves over other units jump higher
+        let delta = m.to - m.from;
+        let steps = delta.x.abs().max(delta.y.abs());
+        let is_line = delta.x == 0 || delta.y == 0 || delta.x.abs() == delta.y.abs();
+        let jumps_over = (1..steps).any(|i| self.get_unit_at(&(m.from + delta / steps * i)).is_some());
+        let jump_height = if !is_line || jumps_over { 25.0 } else { 5.0 };
+        let mut anim = UnitAnimation::new(*m, captured_entity, jump_height);
+        // Attacker that is still where it started only hit the captured unit, which is updated when the hit lands
+        let hit = self.run.unit_at(m.from).and(self.run.unit_at(m.capture_pos()));
+        if hit.is_some() {
+            anim.hit = hit;
+            anim.capture_progress = 0.5;
+        }
+        else {
+            // Unit may have changed in the move, like a jester taking the type of what it captured
+            let unit = self.run.unit_at(m.to).unwrap();
+            *self.world.query_one_mut::<&mut Unit>(entity).unwrap() = unit;
+        }
+        assert!(self.world.insert_one(entity, anim).is_ok());
+        self.update_kings_in_check();
+        self.finish_run(4.0);
     }
 
     fn get_mouse_tile(&self, camera: &Camera2D) -> IVec2 {
@@ -255,106 +421,38 @@ impl GameState {
         m
     }
 
-    fn get_random_empty_tile(&mut self) -> Option<IVec2> {
-        for _ in 0..200 {
-            let x = self.rng.gen_range(0..self.tilemap.get_width());
-            let y = self.rng.gen_range(0..self.tilemap.get_width());
-            let pos = ivec2(x as i32, y as i32);
-
-            if self.tilemap.get(pos) == Some(Tile::Floor) && self.get_unit_at(&pos).is_none() {
-                return Some(pos)
-            }
-        }
-        None
-    }
-
-    fn get_random_empty_away_from_spawn(&mut self, spawn_pos: IVec2) -> Option<IVec2> {
-        for _ in 0..100 {
-            if let Some(pos) = self.get_random_empty_tile() {
-                if utils::dist2(&pos, &spawn_pos) > 4.0f32.powi(2) {
-                    return Some(pos)
-                }
-            }
-        }
-        None
-    }
-
-    fn collect_player_units(&self) -> Vec<(UnitType, InitialPosition)> {
-        let mut q = self.world.query::<(&Unit, &InitialPosition)>();
-        q.iter().map(|(_, (u, p))| (u.unit_type, *p)).collect::<Vec<_>>()
-    }
-
     fn show_shop(&mut self) {
-        let units = self.collect_player_units();
+        self.run.show_shop();
         self.world.clear();
-        self.is_shopping = true;
+        self.clear_searches();
         self.shop_state = ShopState::new();
-
-        for (unit_type, initial) in units {
-            self.add_unit(ivec2(2, 2) + initial.offset, unit_type, Team::Player, Some(initial));
-        }
+        self.spawn_units();
     }
 
-    fn pre_generate_next_floor(&mut self) {
-        let mut gen = MapGenerator::new(self.rng.clone(), 15, 15);
-        self.last_gen_result = Some(gen.generate());
-    }
-
-    fn generate_next_floor(&mut self, player_units: &[(UnitType, InitialPosition)]) {
-        self.floor += 1;
-
-        self.tilemap = self.last_gen_result.as_ref().unwrap().tilemap.clone();
-        let start_pos = self.last_gen_result.as_ref().unwrap().start_pos;
-
-        self.player_turn = true;
+    fn enter_next_floor(&mut self) {
+        self.run.enter_next_floor();
         self.valid_moves_for_selected_unit.clear();
         self.highlighted_moves.clear();
-
         self.world.clear();
-        self.is_shopping = false;
-
-        for &(unit_type, initial) in player_units {
-            self.add_unit(start_pos + initial.offset, unit_type, Team::Player, Some(initial));
-        }
-
-        let mut enemy_units = vec![];
-        let mut enemy_material = 1 + 2 * self.floor as i32;
-        let unit_list = if self.floor <= 2 {
-            &[UnitType::Pawn][..]
-        }
-        else if self.floor <= 4 {
-            &[UnitType::Pawn, UnitType::Knight, UnitType::Bishop][..]
-        }
-        else if self.floor <= 6 {
-            &[UnitType::Pawn, UnitType::Knight, UnitType::Bishop, UnitType::Archbishop][..]
-        }
-        else {
-            &[UnitType::Pawn, UnitType::Knight, UnitType::Bishop, UnitType::Jester, UnitType::Rook][..]
-        };
-
-        while enemy_material > 0 {
-            let available = unit_list.iter().filter(|u| enemy_material >= unit_buy_price(**u)).collect::<Vec<_>>();
-            let index = self.rng.gen_range(0..available.len());
-            let unit = available[index];
-            enemy_material -= unit_buy_price(*unit);
-            enemy_units.push(*unit);
-        }
-
-        if self.floor == LAST_FLOOR {
-            enemy_units.push(UnitType::King);
-        }
+        self.clear_searches();
+        self.spawn_units();
+    }
 
-        while let Some(unit_type) = enemy_units.pop() {
-            if unit_type == UnitType::King {
-                if let Some(p) = self.tilemap.find_tile(Tile::Stairs) {
-                    self.add_unit(p, unit_type, Team::Ai, None);
-                    self.tilemap.set(p, Tile::Floor);
-                }
-            }
-            else if let Some(pos) = self.get_random_empty_away_from_spawn(start_pos) {
-                self.add_unit(pos, unit_type, Team::Ai, None);
+    // Replays an event of a recorded run and shows what happened
+    fn apply_replay_event(&mut self, event: ReplayEvent) -> Result<(), String> {
+        self.run.apply_replay_event(event)?;
+
+        match event {
+            ReplayEvent::PlayerMove(m) | ReplayEvent::AiMove(Some(m)) => self.animate_move(&m),
+            ReplayEvent::AiMove(None) => {}
+            ReplayEvent::GiveUp => self.show_king_lost(),
+            ReplayEvent::NextFloor => {
+                self.world.clear();
+                self.spawn_units();
             }
+            _ => self.spawn_units(),
         }
+        Ok(())
     }
 }
 
@@ -365,39 +463,45 @@ fn animate_units(world: &mut World, board_offset: &Vec2, camera_shake: &mut f32,
     let mut cmd = CommandBuffer::new();
 
     let mut destroyed_entities = vec![];
+    let mut hit_entities = vec![];
 
     for (entity, (pos, unit, anim)) in world.query_mut::<(&mut Vec3, &mut Unit, &mut UnitAnimation)>() {
-        let progress = ((now - anim.start_time) / anim.duration) as f32;
-        let end = utils::tile_pos_to_pixels(&anim.unit_move.to);
+        let mut progress = ((now - anim.start_time) / anim.duration) as f32;
+        // Delete the captured unit when the moving unit reaches it
+        if progress >= anim.capture_progress {
+            if let Some(entity) = anim.captured_unit.take() {
+                match anim.hit {
+                    Some(hit) => hit_entities.push((entity, hit)),
+                    None => {
+                        cmd.despawn(entity);
+                        destroyed_entities.push(entity);
+                    }
+                }
+            }
+        }
         if progress >= 1.0 {
             cmd.remove_one::<UnitAnimation>(entity);
+            // Bouncing unit ends where it started
+            let end = utils::tile_pos_to_pixels(if anim.hit.is_some() { &anim.unit_move.from } else { &anim.unit_move.to });
             *pos = Vec3::new(end.x, end.y, 0.0);
-            // Delete unit at target tile when animation finishes
-            if let Some(entity) = anim.captured_unit {
-                cmd.despawn(entity);
-                destroyed_entities.push(entity);
-            }
             if unit.team == Team::Ai {
                 sound.play("thud");
             }
         }
         else {
-            let delta = anim.unit_move.to - anim.unit_move.from;
-            let is_diagonal = delta.x.abs() == delta.y.abs();
-            let jump_height: f32 =
-                if unit.unit_type == UnitType::Knight || unit.jester_type == UnitType::Knight || (unit.unit_type == UnitType::Archbishop && !is_diagonal) {
-                    25.0
-                }
-                else {
-                    5.0
-                };
-            let smooth_progress = utils::smootherstep(progress);
             let start = utils::tile_pos_to_pixels(&anim.unit_move.from);
+            let mut end = utils::tile_pos_to_pixels(&anim.unit_move.to);
+            // Bouncing back from a hit goes most of the way to the target and returns
+            if anim.hit.is_some() {
+                end = start.lerp(end, 0.7);
+                progress = 1.0 - (2.0 * progress - 1.0).abs();
+            }
+            let smooth_progress = utils::smootherstep(progress);
 
             let pos2 = start.lerp(end, smooth_progress);
             pos.x = pos2.x;
             pos.y = pos2.y;
-            pos.z = -jump_height * utils::halfcircle(smooth_progress);
+            pos.z = -anim.jump_height * utils::halfcircle(smooth_progress);
         }
         animation_going = true;
     }
@@ -414,40 +518,58 @@ fn animate_units(world: &mut World, board_offset: &Vec2, camera_shake: &mut f32,
         }
     }
 
+    for (entity, hit) in hit_entities {
+        if let Ok((pos, unit)) = world.query_one_mut::<(&Vec3, &mut Unit)>(entity) {
+            let text = if hit.armor < unit.armor { "-1 armor" } else { "-1 hp" };
+            effects::add_rising_text(&mut cmd, text, pos.xy() + *board_offset);
+            *unit = hit;
+            *camera_shake = 2.0;
+            sound.play("thud");
+        }
+    }
+
     cmd.run_on(world);
     animation_going
 }
 
-fn start_new_game() -> GameState {
-    let mut gamestate = GameState::new();
-    let units = [(UnitType::King, ivec2(0, 0)), (UnitType::Knight, ivec2(1, 0)), (UnitType::Bishop, ivec2(-1, 0))];
-    gamestate.pre_generate_next_floor();
-    gamestate.generate_next_floor(units.map(|(u, p)| (u, InitialPosition{ offset: p })).as_slice());
-    gamestate
+fn start_new_game(difficulty: Difficulty, engine: EngineKind, predictable: bool, classic_rules: bool, hit_points: bool, seed: u64) -> GameState {
+    GameState::new(Run::new(difficulty, engine, predictable, classic_rules, hit_points, seed))
 }
 
 fn game_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) {
     let is_animation_going = animate_units(&mut gamestate.world, &gamestate.board_offset, &mut gamestate.camera_shake, sound);
-    let player_can_act = gamestate.player_turn && !is_animation_going && gamestate.gameover_timer.is_none() && gamestate.win_timer.is_none();
+    let player_can_act = gamestate.run.player_turn && !is_animation_going && gamestate.gameover_timer.is_none() && gamestate.win_timer.is_none();
 
-    let state = gamestate.get_boardstate();
+    let state = gamestate.run.board_state();
     if state.is_on_stairs() {
         if !is_animation_going {
             gamestate.show_shop();
+            gamestate.save();
             sound.play("stairs");
         }
     }
-    else if !gamestate.player_turn && !is_animation_going {
+    // Giving up cancels the search while it's the AI's turn, and no new one may start after that
+    else if !gamestate.run.player_turn && !is_animation_going && gamestate.gameover_timer.is_none() {
         gamestate.make_ai_move();
     }
 
     draw_rectangle_lines(5.0, 65.0, SCREEN_SIZE.x - 10.0, SCREEN_SIZE.y - 70.0, 4.0, WHITE);
 
     let shaken_offset = gamestate.board_offset + graphics.shake;
-    draw_board(graphics, shaken_offset, &gamestate.tilemap);
+    draw_board(graphics, shaken_offset, &gamestate.run.tilemap);
 
     effects::draw_particles(&mut gamestate.world, graphics);
 
+    if gamestate.threat_overlay {
+        for (pos, count) in state.attack_map(Team::Ai).iter() {
+            let alpha = (0.15 + 0.15 * count as f32).min(0.6);
+            graphics.tint_square(gamestate.board_offset.into(), &pos, Color::new(1.0, 0.0, 0.0, alpha));
+        }
+        for unit in state.units_en_prise(Team::Player) {
+            graphics.highlight_square(gamestate.board_offset.into(), &unit.pos, ORANGE);
+        }
+    }
+
     // Detect when mouse changes tile and highlight moves
     if mouse.old_tile != mouse.tile {
         if let Some((entity, unit)) = gamestate.get_unit_at(&mouse.tile) {
@@ -471,53 +593,91 @@ fn game_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseIn
             graphics.highlight_square(gamestate.board_offset.into(), &m.to, BLUE);
         }
 
+        // Show what the enemy is planning to do
+        if let Some(intent) = gamestate.intent {
+            graphics.draw_arrow(gamestate.board_offset.into(), &intent.from, &intent.to, RED);
+        }
+
+        // Highlight suggested move
+        if let Some(hint) = gamestate.hint {
+            graphics.highlight_square(gamestate.board_offset.into(), &hint.from, YELLOW);
+            graphics.highlight_square(gamestate.board_offset.into(), &hint.to, YELLOW);
+        }
+
         // Highlight selected unit
         if let Some((_, unit, _)) = gamestate.get_selected_unit() {
             graphics.highlight_square(gamestate.board_offset.into(), &unit.pos, DARKBLUE);
         }
 
         // Highlight tile under mouse
-        if let Some(tile) = gamestate.tilemap.get(mouse.tile) {
+        if let Some(tile) = gamestate.run.tilemap.get(mouse.tile) {
             if tile != Tile::Empty {
                 graphics.highlight_square(gamestate.board_offset.into(), &mouse.tile, WHITE);
             }
         }
     }
 
-    draw_units(graphics, shaken_offset, &gamestate.world);
+    draw_units(graphics, shaken_offset, &gamestate.world, gamestate.run.hit_points);
 
     // Top area UI
     {
-        graphics.draw_large_text(if gamestate.player_turn {"Player Turn"} else { "Enemy Turn" }, 10.0, 25.0, &WHITE);
-        graphics.draw_text(format!("Material: {}", gamestate.material).as_str(), 200.0, 40.0, &WHITE);
-        graphics.draw_large_text(format!("Floor {}", gamestate.floor).as_str(), 200.0, 25.0, &WHITE);
-        if !gamestate.highlighted_moves.is_empty() {
-            graphics.draw_text(format!("{:?}", gamestate.highlighted_unit.unit_type).as_str(), 10.0, 40.0, &WHITE);
+        graphics.draw_large_text(if gamestate.run.player_turn {"Player Turn"} else { "Enemy Turn" }, 10.0, 25.0, &WHITE);
+        graphics.draw_text(format!("Material: {}", gamestate.run.material).as_str(), 200.0, 40.0, &WHITE);
+        graphics.draw_large_text(format!("Floor {}", gamestate.run.floor).as_str(), 200.0, 25.0, &WHITE);
+        graphics.draw_text(format!("Seed: {}", gamestate.run.seed).as_str(), 200.0, 55.0, &WHITE);
+        if gamestate.ai_search.is_some() || gamestate.hint_search.is_some() {
+            let dots = ".".repeat((graphics.time * 3.0) as usize % 4);
+            graphics.draw_text(format!("Thinking{dots}").as_str(), 10.0, 40.0, &WHITE);
+        }
+        else if !gamestate.highlighted_moves.is_empty() {
+            graphics.draw_text(unit_status(&gamestate.highlighted_unit, gamestate.run.hit_points).as_str(), 10.0, 40.0, &WHITE);
         }
+        let check = match gamestate.kings_in_check.as_slice() {
+            [Team::Player] => Some(("Your king is in check!", RED)),
+            [Team::Ai] => Some(("Enemy king is in check!", YELLOW)),
+            [_, _] => Some(("Both kings are in check!", ORANGE)),
+            _ => None,
+        };
+        if let Some((text, color)) = check {
+            graphics.draw_text(text, 10.0, 55.0, &color);
+        }
+        if gamestate.debug_overlay {
+            let search = &gamestate.ai_last_search;
+            let y = if check.is_some() { 80.0 } else { 55.0 };
+            graphics.draw_text(format!("AI depth: {} eval: {:.0}", search.depth, search.value).as_str(), 10.0, y, &YELLOW);
+        }
+    }
+
+    gamestate.update_hint();
+    if player_can_act {
+        gamestate.update_intent();
+    }
+    if graphics.draw_button(format!("Hint ({})", gamestate.run.hints_left).as_str(), SCREEN_SIZE.x - 55.0, 38.0, mouse) && player_can_act {
+        gamestate.request_hint();
     }
 
     #[allow(clippy::collapsible_if)]
     if gamestate.gameover_timer.is_none() {
-        if graphics.draw_button("Give up", SCREEN_SIZE.x - 55.0, 8.0, mouse) && player_can_act {
-            for y in 0..gamestate.tilemap.get_height() {
-                for x in 0..gamestate.tilemap.get_width() {
-                    if let Some((entity, unit)) = gamestate.get_unit_at(&ivec2(x as i32, y as i32)) {
-                        if unit.unit_type == UnitType::King && unit.team == Team::Player {
-                            let _ = gamestate.world.despawn(entity);
-                            gamestate.gameover_timer = Some(1.0);
-
-                            let pos = utils::tile_pos_to_pixels(&ivec2(x as i32, y as i32)) + gamestate.board_offset;
-                            let mut cmd = CommandBuffer::new();
-                            effects::add_unit_capture_particles(&mut cmd, pos, unit.team);
-                            cmd.run_on(&mut gamestate.world);
-                        }
-                    }
-                }
-            }
+        let can_give_up = player_can_act || gamestate.ai_search.is_some();
+        if graphics.draw_button("Give up", SCREEN_SIZE.x - 55.0, 8.0, mouse) && can_give_up {
+            gamestate.give_up();
         }
     }
 
-    if macroquad::input::is_mouse_button_pressed(MouseButton::Left) && player_can_act {
+    // Picking what the unit promotes into, the first choice keeps the unit as it is
+    let is_picking_promotion = !gamestate.promotion_moves.is_empty();
+    if is_picking_promotion {
+        if let Some(player_move) = draw_promotion_picker(gamestate, graphics, mouse) {
+            gamestate.select_unit(None);
+            gamestate.make_player_move(&player_move);
+            sound.play("thud");
+        }
+        else if macroquad::input::is_mouse_button_pressed(MouseButton::Left) || macroquad::input::is_mouse_button_pressed(MouseButton::Right) {
+            gamestate.select_unit(None);
+        }
+    }
+
+    if macroquad::input::is_mouse_button_pressed(MouseButton::Left) && player_can_act && !is_picking_promotion {
         if let Some((entity, unit)) = gamestate.get_unit_at(&mouse.tile) {
             if unit.team == Team::Player {
                 gamestate.select_unit(Some(entity));
@@ -526,39 +686,71 @@ fn game_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseIn
         }
     }
 
-    if macroquad::input::is_mouse_button_released(MouseButton::Left) && player_can_act {
-        if let Some((e, _, _)) = gamestate.get_selected_unit() {
-            if let Some(player_move) = gamestate.valid_moves_for_selected_unit.iter().find(|m| m.to == mouse.tile).copied() {
-                gamestate.make_player_move(e, &player_move);
-                gamestate.select_unit(None);
-                sound.play("thud");
-            }
+    if macroquad::input::is_mouse_button_released(MouseButton::Left) && player_can_act && !is_picking_promotion && gamestate.get_selected_unit().is_some() {
+        let moves = gamestate.valid_moves_for_selected_unit.iter().filter(|m| m.to == mouse.tile).copied().collect::<Vec<_>>();
+        if let [player_move] = moves.as_slice() {
+            gamestate.make_player_move(player_move);
+            gamestate.select_unit(None);
+            sound.play("thud");
+        }
+        else if moves.len() > 1 {
+            gamestate.promotion_moves = moves;
         }
     }
 
+    if macroquad::input::is_key_pressed(KeyCode::T) {
+        gamestate.threat_overlay = !gamestate.threat_overlay;
+    }
+
+    if macroquad::input::is_key_pressed(KeyCode::F3) {
+        gamestate.debug_overlay = !gamestate.debug_overlay;
+    }
+
+    // Log the position for bug reports and AI tests
+    if macroquad::input::is_key_pressed(KeyCode::F4) {
+        let side_to_move = if gamestate.run.player_turn { Team::Player } else { Team::Ai };
+        miniquad::info!("Position: {}", Position::new(gamestate.run.board_state(), side_to_move));
+    }
+
     // TODO: remove
     if macroquad::input::is_key_pressed(KeyCode::W) {
         gamestate.show_shop();
     }
 }
 
-fn find_pos_for_new_unit(gamestate: &GameState) -> Option<(IVec2, InitialPosition)> {
-    for y in 0..5 {
-        for x in 0..5 {
-            if gamestate.get_unit_at(&ivec2(x, y)).is_none() {
-                return Some((ivec2(x, y), InitialPosition { offset: ivec2(x - 2, y - 2) }));
+// Returns the move that was clicked
+fn draw_promotion_picker(gamestate: &GameState, graphics: &Graphics, mouse: &MouseInfo) -> Option<Move> {
+    let (_, unit, _) = gamestate.get_selected_unit()?;
+    let moves = &gamestate.promotion_moves;
+    let w = TILE_SIZEF * (2.0 * moves.len() as f32 + 1.0);
+    let h = TILE_SIZEF * 5.0;
+    let (x, y) = ((SCREEN_SIZE.x - w) / 2.0, 150.0);
+    draw_rectangle(x, y, w, h, BLACK);
+    draw_rectangle_lines(x, y, w, h, 4.0, WHITE);
+
+    let mut title = "Promote into".to_owned();
+    let mut clicked = None;
+    for (i, m) in moves.iter().enumerate() {
+        let unit_type = m.promotion.unwrap_or(unit.unit_type);
+        let (unit_x, unit_y) = (x + TILE_SIZEF + 2.0 * TILE_SIZEF * i as f32, y + TILE_SIZEF);
+        graphics.draw_sprite(unit_into_sprite(&Unit { team: Team::Player, unit_type, ..default() }), 0, unit_x, unit_y, &WHITE, true);
+        graphics.draw_text(promotion_cost(unit.unit_type, unit_type).to_string().as_str(), unit_x + 3.0, unit_y + TILE_SIZEF * 3.0, &WHITE);
+        if Rect::new(unit_x, unit_y, TILE_SIZEF, TILE_SIZEF * 2.0).contains(mouse.pos) {
+            title = format!("Promote into {unit_type:?}");
+            if macroquad::input::is_mouse_button_pressed(MouseButton::Left) {
+                clicked = Some(*m);
             }
         }
     }
-    None
+    graphics.draw_text(&title, x + 8.0, y + 12.0, &WHITE);
+    clicked
 }
 
-fn shop_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) {
-    static SHOP_MAP: Lazy<TileMap> = Lazy::new(|| {
-        TileMap::from([".....", ".....", ".....", ".....", "....."].as_slice())
-    });
+static SHOP_MAP: Lazy<TileMap> = Lazy::new(|| {
+    TileMap::from([".....", ".....", ".....", ".....", "....."].as_slice())
+});
 
-    let shop_pieces = [UnitType::Pawn, UnitType::Knight, UnitType::Bishop, UnitType::Jester, UnitType::Rook, UnitType::Archbishop, UnitType::Queen];
+fn shop_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) {
 
     let _ = animate_units(&mut gamestate.world, &gamestate.board_offset, &mut gamestate.camera_shake, sound);
 
@@ -578,18 +770,22 @@ fn shop_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseIn
         graphics.draw_text("their starting positions", 160.0, 100.0, &WHITE);
         graphics.draw_text("Drag unit outside of", 160.0, 130.0, &WHITE);
         graphics.draw_text("the board to sell it", 160.0, 145.0, &WHITE);
+        if gamestate.run.hit_points {
+            graphics.draw_text("Right click unit to", 160.0, 160.0, &WHITE);
+            graphics.draw_text(format!("buy armor for {ARMOR_PRICE}").as_str(), 160.0, 175.0, &WHITE);
+        }
 
         graphics.draw_text("Click unit to buy it", 200.0, 220.0, &WHITE);
 
         graphics.draw_large_text("Shop", 20.0, 220.0, &WHITE);
-        graphics.draw_text(format!("Material: {}", gamestate.material).as_str(), 100.0, 220.0, &WHITE);
+        graphics.draw_text(format!("Material: {}", gamestate.run.material).as_str(), 100.0, 220.0, &WHITE);
 
         graphics.draw_text("Cost:", 10.0, 300.0, &WHITE);
 
-        for (i, &unit_type) in shop_pieces.iter().enumerate() {
+        for (i, unit_type) in shop_units().into_iter().enumerate() {
             let x = 50.0 + 2.0 * TILE_SIZEF * i as f32;
             let y = 240.0;
-            let color = if gamestate.material >= unit_buy_price(unit_type) { WHITE } else { GRAY };
+            let color = if gamestate.run.material >= unit_buy_price(unit_type) { WHITE } else { GRAY };
             graphics.draw_sprite(unit_into_sprite(&Unit { team: Team::Player, unit_type, ..default() }), 0, x, y, &color, true);
             let unit_rect = Rect::new(x, y, TILE_SIZEF, TILE_SIZEF * 2.0);
             if unit_rect.contains(mouse.pos) {
@@ -602,8 +798,7 @@ fn shop_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseIn
         }
 
         if graphics.draw_button("Start next level", SCREEN_SIZE.x - 120.0, SCREEN_SIZE.y - 30.0, mouse) {
-            gamestate.pre_generate_next_floor();
-            let start_pos = utils::tile_pos_to_pixels(&gamestate.last_gen_result.as_ref().unwrap().start_pos);
+            let start_pos = utils::tile_pos_to_pixels(&gamestate.run.pre_generate_next_floor());
             gamestate.shop_state.board_anim = Some(start_pos + vec2(0.0, 40.0));
         }
     }
@@ -614,8 +809,8 @@ fn shop_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseIn
             gamestate.shop_state.board_offset.lerp(offset, 1.0 - 0.05_f32.powf(delta_time));
 
         if gamestate.shop_state.board_offset.distance_squared(offset) < 2.0 * 2.0 {
-            let units = gamestate.collect_player_units();
-            gamestate.generate_next_floor(&units);
+            gamestate.enter_next_floor();
+            gamestate.save();
         }
     }
 
@@ -626,9 +821,18 @@ fn shop_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseIn
     if is_valid_tile && gamestate.shop_state.board_anim.is_none() {
         graphics.highlight_square(gamestate.shop_state.board_offset.into(), &mouse.tile, BLUE);
     }
-    draw_units(graphics, gamestate.shop_state.board_offset, &gamestate.world);
+    draw_units(graphics, gamestate.shop_state.board_offset, &gamestate.world, gamestate.run.hit_points);
 
     if gamestate.shop_state.board_anim.is_none() {
+        if let Some((_, unit)) = gamestate.get_unit_at(&mouse.tile) {
+            graphics.draw_text(unit_status(&unit, gamestate.run.hit_points).as_str(), 20.0, 55.0, &WHITE);
+        }
+
+        // King can be armored too, even though it can't be moved
+        if macroquad::input::is_mouse_button_pressed(MouseButton::Right) && gamestate.run.buy_armor(mouse.tile) {
+            gamestate.spawn_units();
+        }
+
         if macroquad::input::is_mouse_button_pressed(MouseButton::Left) {
             if is_valid_tile {
                 if let Some((entity, _)) = gamestate.get_unit_at(&mouse.tile) {
@@ -636,12 +840,8 @@ fn shop_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseIn
                 }
             }
             else if let Some(unit_type) = selected_shop_unit {
-                if let Some((pos, ipos)) = find_pos_for_new_unit(gamestate) {
-                    let price = unit_buy_price(unit_type);
-                    if gamestate.material >= price {
-                        gamestate.add_unit(pos, unit_type, Team::Player, Some(ipos));
-                        gamestate.material -= price;
-                    }
+                if gamestate.run.buy_unit(unit_type) {
+                    gamestate.spawn_units();
                 }
             }
         }
@@ -653,64 +853,157 @@ fn shop_loop(gamestate: &mut GameState, graphics: &mut Graphics, mouse: &MouseIn
         }
 
         if macroquad::input::is_mouse_button_released(MouseButton::Left) {
-            // Find out if an unit needs to swap position
-            let mut swap_entity = None;
-            let mut swap_to = None;
-            if is_valid_tile {
-                if let Some((e, _)) = gamestate.get_unit_at(&target_tile) {
-                    swap_entity = Some(e);
-                }
-            }
-
-            // Move dragged unit to its place
-            let mut sold_unit = None;
-            for (e, (pos, unit, ipos, _)) in gamestate.world.query_mut::<(&mut Vec3, &mut Unit, &mut InitialPosition, &ShopDrag)>() {
-                let mut target_tile = target_tile;
-                if !is_valid_tile {
-                    if target_tile == ivec2(2, 2) {
-                        target_tile = unit.pos;
-                    }
-                    else {
-                        sold_unit = Some((e, unit.unit_type));
-                    }
-                }
-                let p = utils::tile_pos_to_pixels(&target_tile);
-                pos.x = p.x;
-                pos.y = p.y;
-                pos.z = 0.0;
-                swap_to = Some(unit.pos);
-                unit.pos = target_tile;
-                ipos.offset = unit.pos - ivec2(2, 2);
-            }
+            let dragged = gamestate.world.query_mut::<(&Unit, &ShopDrag)>().into_iter().next().map(|(_, (u, _))| u.pos);
             utils::delete_all_components::<ShopDrag>(&mut gamestate.world);
 
-            // Sell unit
-            if let Some((entity, unit_type)) = sold_unit {
-                gamestate.material += unit_buy_price(unit_type);
-                assert!(gamestate.world.despawn(entity).is_ok());
-            }
-
-            // Move swapped unit to the dragged unit's original position
-            if let Some(swap_entity) = swap_entity {
-                if let Some(swap_pos) = swap_to {
-                    if let Ok((pos, unit, ipos)) = gamestate.world.query_one_mut::<(&mut Vec3, &mut Unit, &mut InitialPosition)>(swap_entity) {
-                        unit.pos = swap_pos;
-                        ipos.offset = unit.pos - ivec2(2, 2);
-                        let p = utils::tile_pos_to_pixels(&unit.pos);
-                        pos.x = p.x;
-                        pos.y = p.y;
-                        pos.z = 0.0;
-                    }
+            if let Some(from) = dragged {
+                // Dropping the unit on the king puts it back where it was
+                if is_valid_tile {
+                    gamestate.run.place_unit(from, target_tile);
                 }
+                else if target_tile != ivec2(2, 2) {
+                    gamestate.run.sell_unit(from);
+                }
+                gamestate.spawn_units();
             }
         }
     }
 }
 
-fn menu_loop(graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) -> bool {
-    draw_rectangle_lines(30.0, 120.0, SCREEN_SIZE.x - 60.0, SCREEN_SIZE.y - 240.0, 4.0, WHITE);
-    graphics.draw_large_text("King's Conquest", 90.0, 160.0, &WHITE);
-    if graphics.draw_button("Click to start", 150.0, 220.0, mouse) {
+struct ReplayViewer {
+    gamestate: GameState,
+    events: Vec<ReplayEvent>,
+    next_event: usize,
+    playing: bool,
+    step_timer: f32,
+    error: Option<String>,
+}
+
+impl ReplayViewer {
+    fn new(replay: Replay) -> Self {
+        let mut gamestate = start_new_game(replay.difficulty, replay.engine, replay.predictable, replay.classic_rules, replay.hit_points, replay.seed);
+        gamestate.run.replay = None;
+        ReplayViewer { gamestate, events: replay.events, next_event: 0, playing: true, step_timer: 0.0, error: None }
+    }
+}
+
+const REPLAY_STEP_DELAY: f32 = 0.5;
+
+// Returns false when the viewer is closed
+fn replay_loop(viewer: &mut ReplayViewer, graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) -> bool {
+    let gamestate = &mut viewer.gamestate;
+    let delta_time = macroquad::time::get_frame_time();
+    effects::update(&mut gamestate.world, delta_time);
+    let is_animation_going = animate_units(&mut gamestate.world, &gamestate.board_offset, &mut gamestate.camera_shake, sound);
+
+    if !is_animation_going && gamestate.run.is_on_stairs() {
+        gamestate.show_shop();
+        sound.play("stairs");
+    }
+
+    let finished = viewer.next_event >= viewer.events.len() || viewer.error.is_some();
+    let mut step = false;
+    if graphics.draw_button(if viewer.playing { "Pause" } else { "Play" }, SCREEN_SIZE.x - 55.0, 8.0, mouse) {
+        viewer.playing = !viewer.playing;
+    }
+    if graphics.draw_button("Step", SCREEN_SIZE.x - 55.0, 38.0, mouse) {
+        viewer.playing = false;
+        step = true;
+    }
+    if viewer.playing && !is_animation_going {
+        viewer.step_timer += delta_time;
+        if viewer.step_timer >= REPLAY_STEP_DELAY {
+            viewer.step_timer = 0.0;
+            step = true;
+        }
+    }
+
+    if step && !is_animation_going && !finished {
+        let event = viewer.events[viewer.next_event];
+        match gamestate.apply_replay_event(event) {
+            Ok(()) => viewer.next_event += 1,
+            Err(e) => viewer.error = Some(e),
+        }
+    }
+
+    let offset = if gamestate.run.is_shopping { gamestate.shop_state.board_offset } else { gamestate.board_offset };
+    draw_board(graphics, offset, if gamestate.run.is_shopping { &SHOP_MAP } else { &gamestate.run.tilemap });
+    effects::draw_particles(&mut gamestate.world, graphics);
+    draw_units(graphics, offset, &gamestate.world, gamestate.run.hit_points);
+    effects::draw(&mut gamestate.world, graphics);
+
+    graphics.draw_large_text("Replay", 10.0, 25.0, &WHITE);
+    graphics.draw_text(format!("Step {}/{}", viewer.next_event, viewer.events.len()).as_str(), 10.0, 40.0, &WHITE);
+    if let Some(error) = &viewer.error {
+        graphics.draw_text(format!("Out of sync: {error}").as_str(), 10.0, 55.0, &RED);
+    }
+    graphics.draw_large_text(format!("Floor {}", gamestate.run.floor).as_str(), 200.0, 25.0, &WHITE);
+    graphics.draw_text(format!("Material: {}", gamestate.run.material).as_str(), 200.0, 40.0, &WHITE);
+    graphics.draw_text(format!("Seed: {}", gamestate.run.seed).as_str(), 200.0, 55.0, &WHITE);
+
+    !graphics.draw_button("Exit", SCREEN_SIZE.x - 110.0, 8.0, mouse)
+}
+
+fn menu_loop(gamestate: &mut GameState, seed_input: &mut String, replay_viewer: &mut Option<ReplayViewer>, graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) -> bool {
+    while let Some(c) = get_char_pressed() {
+        if c.is_ascii_digit() && seed_input.len() < 18 {
+            seed_input.push(c);
+        }
+    }
+    if is_key_pressed(KeyCode::Backspace) {
+        seed_input.pop();
+    }
+
+    draw_rectangle_lines(30.0, 100.0, SCREEN_SIZE.x - 60.0, SCREEN_SIZE.y - 132.0, 4.0, WHITE);
+    graphics.draw_large_text("King's Conquest", 90.0, 140.0, &WHITE);
+    if graphics.draw_button(format!("Difficulty: {:?}", gamestate.run.difficulty).as_str(), 125.0, 162.0, mouse) {
+        gamestate.run.difficulty = enum_iterator::next_cycle(&gamestate.run.difficulty).unwrap();
+        sound.play("thud3");
+    }
+    if graphics.draw_button(format!("AI: {:?}", gamestate.run.engine).as_str(), 125.0, 186.0, mouse) {
+        gamestate.run.engine = enum_iterator::next_cycle(&gamestate.run.engine).unwrap();
+        sound.play("thud3");
+    }
+    if graphics.draw_button(format!("Predictable AI: {}", if gamestate.run.predictable { "On" } else { "Off" }).as_str(), 125.0, 210.0, mouse) {
+        gamestate.run.predictable = !gamestate.run.predictable;
+        sound.play("thud3");
+    }
+    if graphics.draw_button(format!("Classic rules: {}", if gamestate.run.classic_rules { "On" } else { "Off" }).as_str(), 125.0, 234.0, mouse) {
+        gamestate.run.classic_rules = !gamestate.run.classic_rules;
+        sound.play("thud3");
+    }
+    if graphics.draw_button(format!("Hit points: {}", if gamestate.run.hit_points { "On" } else { "Off" }).as_str(), 125.0, 258.0, mouse) {
+        gamestate.run.hit_points = !gamestate.run.hit_points;
+        sound.play("thud3");
+    }
+    let seed_text = if seed_input.is_empty() { "random" } else { seed_input.as_str() };
+    graphics.draw_text(format!("Seed: {seed_text}").as_str(), 125.0, 288.0, &WHITE);
+    if has_save() && graphics.draw_button("Continue", 245.0, 303.0, mouse) {
+        match read_save() {
+            Ok(data) => {
+                *gamestate = GameState::from_save_data(data);
+                sound.play("thud2");
+                return false
+            }
+            Err(e) => {
+                miniquad::error!("Failed to load the game: {}", e);
+                delete_save();
+            }
+        }
+    }
+    if has_replay() && graphics.draw_button("Watch last run", 150.0, 328.0, mouse) {
+        match read_replay() {
+            Ok(replay) => {
+                *replay_viewer = Some(ReplayViewer::new(replay));
+                sound.play("thud2");
+            }
+            Err(e) => miniquad::error!("Failed to load the replay: {}", e),
+        }
+    }
+    if graphics.draw_button("Click to start", 150.0, 303.0, mouse) {
+        let seed = seed_input.parse().unwrap_or_else(|_| random_seed());
+        *gamestate = start_new_game(gamestate.run.difficulty, gamestate.run.engine, gamestate.run.predictable, gamestate.run.classic_rules, gamestate.run.hit_points, seed);
+        gamestate.save();
         sound.play("thud2");
         false
     }
@@ -719,9 +1012,10 @@ fn menu_loop(graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) -> bool
     }
 }
 
-fn gameover_loop(graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) -> bool {
+fn gameover_loop(seed: u64, graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) -> bool {
     draw_rectangle_lines(30.0, 120.0, SCREEN_SIZE.x - 60.0, SCREEN_SIZE.y - 240.0, 4.0, WHITE);
     graphics.draw_large_text("Game over", 135.0, 160.0, &WHITE);
+    graphics.draw_text(format!("Seed: {seed}").as_str(), 135.0, 190.0, &WHITE);
     if graphics.draw_button("Click to restart", 150.0, 220.0, mouse) {
         sound.play("thud2");
         true
@@ -731,10 +1025,11 @@ fn gameover_loop(graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) -> b
     }
 }
 
-fn win_loop(graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) -> bool {
+fn win_loop(seed: u64, graphics: &mut Graphics, mouse: &MouseInfo, sound: &Sound) -> bool {
     draw_rectangle_lines(30.0, 120.0, SCREEN_SIZE.x - 60.0, SCREEN_SIZE.y - 240.0, 4.0, WHITE);
     graphics.draw_large_text("You're", 145.0, 160.0, &WHITE);
     graphics.draw_large_text("winner !", 145.0, 180.0, &WHITE);
+    graphics.draw_text(format!("Seed: {seed}").as_str(), 145.0, 200.0, &WHITE);
     if graphics.draw_button("Click to restart", 150.0, 220.0, mouse) {
         sound.play("thud2");
         true
@@ -762,13 +1057,26 @@ async fn main() {
 
     let mut graphics = Graphics::new().await;
     let sound = Sound::new().await;
+    // std has no clock on the web
+    set_clock(|| miniquad::date::now() * 1000.0);
 
     let mut mainmenu = true;
 
-    let mut gamestate = start_new_game();
+    let mut gamestate = start_new_game(Difficulty::default(), EngineKind::default(), false, false, false, random_seed());
+    let mut seed_input = String::new();
+    let mut replay_viewer = None;
     let mut mouse = MouseInfo::default();
 
+    prevent_quit();
+
     loop {
+        if is_quit_requested() {
+            if !mainmenu {
+                gamestate.save();
+            }
+            break;
+        }
+
         set_camera(&ingame_camera);
         clear_background(BLACK);
 
@@ -780,8 +1088,8 @@ async fn main() {
         mouse.delta = mouse.pos - mouse.old_pos;
 
         if gamestate.camera_shake > 0.0 {
-            let x = gamestate.rng.gen_range(-1.0..1.0);
-            let y = gamestate.rng.gen_range(-1.0..1.0);
+            let x = gamestate.cosmetic_rng.gen_range(-1.0..1.0);
+            let y = gamestate.cosmetic_rng.gen_range(-1.0..1.0);
             graphics.shake = gamestate.camera_shake * vec2(x, y);
 
             gamestate.camera_shake = effects::lerp(gamestate.camera_shake, 0.0, 1.0 - 0.01_f32.powf(delta_time));
@@ -803,23 +1111,28 @@ async fn main() {
             }
         }
 
-        if mainmenu {
-            mainmenu = menu_loop(&mut graphics, &mouse, &sound);
+        if let Some(viewer) = &mut replay_viewer {
+            if !replay_loop(viewer, &mut graphics, &mouse, &sound) {
+                replay_viewer = None;
+            }
+        }
+        else if mainmenu {
+            mainmenu = menu_loop(&mut gamestate, &mut seed_input, &mut replay_viewer, &mut graphics, &mouse, &sound);
         }
         else if is_gameover {
-            if gameover_loop(&mut graphics, &mouse, &sound) {
-                gamestate = start_new_game();
+            if gameover_loop(gamestate.run.seed, &mut graphics, &mouse, &sound) {
+                gamestate = start_new_game(gamestate.run.difficulty, gamestate.run.engine, gamestate.run.predictable, gamestate.run.classic_rules, gamestate.run.hit_points, random_seed());
             }
         }
         else if is_win {
-            if win_loop(&mut graphics, &mouse, &sound) {
-                gamestate = start_new_game();
+            if win_loop(gamestate.run.seed, &mut graphics, &mouse, &sound) {
+                gamestate = start_new_game(gamestate.run.difficulty, gamestate.run.engine, gamestate.run.predictable, gamestate.run.classic_rules, gamestate.run.hit_points, random_seed());
             }
         }
         else {
             effects::update(&mut gamestate.world, delta_time);
 
-            if gamestate.is_shopping {
+            if gamestate.run.is_shopping {
                 shop_loop(&mut gamestate, &mut graphics, &mouse, &sound);
             }
             else {
@@ -3,14 +3,6 @@ use macroquad::prelude::{IVec2, Vec2};
 
 use crate::graphics::TILE_SIZEF;
 
-pub fn dist(v1: &IVec2, v2: &IVec2) -> f32 {
-    f32::sqrt(dist2(v1, v2))
-}
-
-pub fn dist2(v1: &IVec2, v2: &IVec2) -> f32 {
-    ((v1.x - v2.x) * (v1.x - v2.x) + (v1.y - v2.y) * (v1.y - v2.y)) as f32
-}
-
 pub fn delete_all_components<T: Send + Sync + 'static>(world: &mut World) {
     let mut cmd = CommandBuffer::new();
     for (e, _) in world.query_mut::<&T>() {